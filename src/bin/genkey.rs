@@ -1,54 +1,41 @@
 extern crate ipfsrs;
-extern crate openssl;
 extern crate rustc_serialize;
-extern crate rust_base58;
-extern crate rust_multihash;
 extern crate protobuf;
 
 use rustc_serialize::base64::{ToBase64,FromBase64, Config, CharacterSet, Newline};
-use rust_multihash::{multihash, HashTypes};
-use rust_base58::base58::ToBase58;
 use protobuf::core::Message;
 
-use ipfsrs::crypto;
+use ipfsrs::crypto::KeyType;
+use ipfsrs::keys;
+use ipfsrs::keystore::Keystore;
+use ipfsrs::peer::PeerId;
 
 use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
-use std::io::Write;
 
 static key_count: AtomicUsize = ATOMIC_USIZE_INIT;
 const MAX_KEYS: usize = 5000;
 
 
-fn run() {
-    use openssl::crypto::pkey::PKey;
+fn run(key_type: KeyType) {
     let wants = vec!();
 
     loop {
-        let mut pkey = PKey::new();
-        pkey.gen(2048);
+        let seckey_pb = keys::generate(key_type, 2048).unwrap();
 
         //println!("Pubkey: {}", pubkey_encoded);
 
-        let mut pubkey_pb = crypto::PublicKey::new();
-        pubkey_pb.set_Data(pkey.save_pub());
-        pubkey_pb.set_Type(crypto::KeyType::RSA);
-        let pubkey_bytes = pubkey_pb.write_to_bytes().unwrap();
-        let pubkey_mh = multihash(HashTypes::SHA2256, pubkey_bytes).unwrap();
-        let peerid = pubkey_mh.to_base58();
+        let peerid = PeerId::from_private_key(&seckey_pb).unwrap().to_base58();
         let short = peerid.to_lowercase();
         if wants.iter().any(|x| short.contains(x)) {
-            println!("PeerID: \"{}\"", pubkey_mh.to_base58());
+            println!("PeerID: \"{}\"", peerid);
 
-            let mut seckey_pb = crypto::PrivateKey::new();
-            seckey_pb.set_Data(pkey.save_priv());
-            seckey_pb.set_Type(crypto::KeyType::RSA);
             let seckey_bytes = seckey_pb.write_to_bytes().unwrap();
             //let seckey_mh = multihash(HashTypes::SHA2256, seckey_bytes.clone()).unwrap();
             let cfg = Config{char_set: CharacterSet::Standard, newline: Newline::LF, pad: true, line_length: None};
             let seckey_encoded = &seckey_bytes.to_base64(cfg);
             println!("PrivKey: \"{}\"", seckey_encoded);
-            if let Ok(mut f) = std::fs::File::create(&peerid) {
-                f.write_all(seckey_encoded.as_bytes());
+            if let Err(e) = Keystore::from_env().put(&peerid, &seckey_pb) {
+                println!("Couldn't save key: {}", e);
             }
         }
 
@@ -65,13 +52,21 @@ fn run() {
 fn main() {
     use std::thread;
 
+    // usage: genkey [rsa|ed25519]
+    let key_type = match std::env::args().nth(1) {
+        None => KeyType::RSA,
+        Some(ref s) if s == "rsa" => KeyType::RSA,
+        Some(ref s) if s == "ed25519" => KeyType::Ed25519,
+        Some(s) => {
+            println!("Unknown key type {:?}; expected rsa or ed25519", s);
+            return;
+        }
+    };
+
     let mut threads = Vec::new();
-    threads.push(thread::spawn(|| run()));
-    threads.push(thread::spawn(|| run()));
-    threads.push(thread::spawn(|| run()));
-    threads.push(thread::spawn(|| run()));
-    threads.push(thread::spawn(|| run()));
-    threads.push(thread::spawn(|| run()));
+    for _ in 0..6 {
+        threads.push(thread::spawn(move || run(key_type)));
+    }
     loop {
         thread::sleep_ms(10000);
         let count = key_count.load(Ordering::Relaxed);
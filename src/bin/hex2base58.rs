@@ -7,7 +7,7 @@ use std::env;
 
 fn main() {
     let input = env::args().nth(1).unwrap();
-    let mut bin = hex_to_bin(&input);
+    let bin = hex_to_bin(&input).unwrap();
 
     println!("{}", bin.to_base58());
 
@@ -8,7 +8,7 @@ use rust_multihash::HashTypes;
 use rust_base58::ToBase58;
 
 fn hex2mh(input: &str) -> String {
-    let mut bin = hex_to_bin(input);
+    let mut bin = hex_to_bin(input).unwrap();
     let bin_len = bin.len() as u8;
     let hash = HashTypes::SHA2256.to_u8();
     bin.insert(0, bin_len);
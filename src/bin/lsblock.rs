@@ -6,24 +6,18 @@ extern crate ipfsrs;
 
 use ipfsrs::unixfs::Data;
 use ipfsrs::merkledag::PBNode;
-use ipfsrs::{get_blockfile_from_hash};
+use ipfsrs::blockstore::{Blockstore, FsBlockstore, verify_block};
 use ipfsrs::multihash::*;
-
-use std::fs::File;
-use rust_multihash::{HashTypes, multihash};
-use std::io::Read;
 use protobuf::core::Message;
 use rust_base58::ToBase58;
 
 fn main() {
     use std::env;
 
+    let store = FsBlockstore::from_env();
     let hash = MultihashStr(env::args().nth(1).unwrap());
-    let blockfile = get_blockfile_from_hash(hash);
-    println!("opening {:?}", blockfile);
-    let mut f = File::open(blockfile).unwrap();
-    let mut bytes = Vec::new();
-    f.read_to_end(&mut bytes);
+    println!("opening {:?}", store.path_for(&hash).unwrap());
+    let bytes = store.get_unverified(&hash).unwrap_or_else(|e| panic!("failed to read block: {}", e));
 
     let mut pbnode =  PBNode::new();
     pbnode.merge_from_bytes(&bytes).unwrap_or_else(|e| panic!("failed to merge from bytes: {:?}", e));
@@ -58,13 +52,14 @@ fn main() {
     println!("num links: {}", links.len());
     for link in links {
         let pretty_hashname = link.get_Hash().to_base58();
-        let hashfile = get_blockfile_from_hash(MultihashBytes(link.get_Hash().to_vec()));
         println!("  link {} -> {:?} {}", link.get_Name(), pretty_hashname, link.get_Tsize());
         total_size += (link.get_Tsize() as u64);
 
     }
     println!("Total size of this block and all links: {} bytes", total_size);
-    let new_mh = multihash(HashTypes::SHA2512, bytes).unwrap();
-    println!("Recalculated multihash for this object: {}", new_mh.to_base58());
+    match verify_block(&hash, &bytes) {
+        Ok(()) => println!("Block contents match the multihash"),
+        Err(e) => println!("Block failed verification: {}", e)
+    }
 
 }
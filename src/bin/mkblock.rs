@@ -1,37 +1,23 @@
-#![feature(path_ext_deprecated)]
-
 extern crate ipfsrs;
 extern crate rust_base58;
 extern crate rust_multihash;
 extern crate protobuf;
 
 use ipfsrs::*;
+use ipfsrs::blockstore::{Blockstore, FsBlockstore};
+use ipfsrs::multihash::MultihashBytes;
 use rust_multihash::{multihash, HashTypes};
 use protobuf::core::Message;
 use protobuf::repeated::RepeatedField;
 use rust_base58::ToBase58;
-use std::fs::File;
 
 
 // returns the hash of the object written
-fn write_data_to_fs(data: &merkledag::PBNode) -> Vec<u8> {
-    use std::path::PathBuf;
-    use std::fs::PathExt;
+fn write_data_to_fs<B: Blockstore>(store: &mut B, data: &merkledag::PBNode) -> Vec<u8> {
     let bytes = data.write_to_bytes().unwrap();
     let mh = multihash(HashTypes::SHA2256, bytes.clone()).unwrap();
-    let hash = bin_to_hex(&mh);
-
-    let prefix = &hash[0..8];
-    let mut block_dir = PathBuf::from("/storage/home/achin/.ipfs/blocks");
-    block_dir.push(prefix);
-    if !block_dir.exists() {
-        std::fs::create_dir(&block_dir);
-    }
 
-    block_dir.push(&hash);
-    block_dir.set_extension("data");
-    let mut f = File::create(&block_dir).unwrap();
-    data.write_to_writer(&mut f);
+    store.put(MultihashBytes(mh.clone()), &bytes).unwrap();
 
     println!("Data written: {}", mh.to_base58());
 
@@ -48,6 +34,8 @@ fn build_node_with_data(data: &unixfs::Data) -> merkledag::PBNode {
 
     
 fn main() {
+    let mut store = FsBlockstore::from_env();
+
     // create a unixfs node with a "hello world" data
     let unix1 = build_unixfs("hello world");
     let unix1_bytes = unix1.write_to_bytes().unwrap();
@@ -55,15 +43,15 @@ fn main() {
     // create a merklenode with this data
     let merkle1 = build_node_with_data(&unix1);
     println!("Writing example hash 1:");
-    write_data_to_fs(&merkle1);
+    write_data_to_fs(&mut store, &merkle1);
 
 
     // create a unixfs node with a "hello " data, and another rnode with a "world" data
     let unix2 = build_node_with_data(&build_unixfs("hello "));
-    let unix2_hash = write_data_to_fs(&unix2);
+    let unix2_hash = write_data_to_fs(&mut store, &unix2);
 
     let unix3 = build_node_with_data(&build_unixfs("world"));
-    let unix3_hash = write_data_to_fs(&unix3);
+    let unix3_hash = write_data_to_fs(&mut store, &unix3);
     
     let mut merkle2 = merkledag::PBNode::new();
 
@@ -103,7 +91,7 @@ fn main() {
     println!("Calculated multihash for this object: {}", new_mh.to_base58());
     println!("Written to {}", bin_to_hex(&new_mh));
 
-    write_data_to_fs(&merkle2);
+    write_data_to_fs(&mut store, &merkle2);
     }
 
 }
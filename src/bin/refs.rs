@@ -0,0 +1,46 @@
+extern crate ipfsrs;
+
+use std::env;
+use std::io::{self, Write};
+use std::process;
+
+use ipfsrs::blockstore::FsBlockstore;
+use ipfsrs::multihash::MultihashStr;
+use ipfsrs::walk::{walk, WalkOptions};
+
+// Prints every block below the given hash, like `ipfs refs -r --unique`
+fn main() {
+    let hash = match env::args().nth(1) {
+        Some(hash) => MultihashStr(hash),
+        None => {
+            let _ = writeln!(io::stderr(), "usage: refs <hash>");
+            process::exit(2);
+        }
+    };
+
+    let store = FsBlockstore::from_env();
+    let opts = WalkOptions{ unique: true, ..WalkOptions::default() };
+    let visits = match walk(&store, hash, opts) {
+        Ok(visits) => visits,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    // keep going past bad blocks, but say so in the exit status
+    let mut failed = false;
+    for visit in visits {
+        match visit {
+            Ok(ref v) if v.depth == 0 => {}
+            Ok(v) => println!("{}", v.node.mh_str.0),
+            Err(e) => {
+                let _ = writeln!(io::stderr(), "error: {}", e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
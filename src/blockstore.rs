@@ -0,0 +1,278 @@
+//! Storage for raw blocks, keyed by multihash
+//!
+//! Everything that needs to read or write blocks (the merkledag helpers, unixfs, the binaries)
+//! goes through the `Blockstore` trait, so it can be pointed at the on-disk repo or at an
+//! in-memory store for tests.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use error::{Error, Result};
+use multihash::{Multihash, MultihashBytes, ParsedMultihash};
+use ::{hash_bytes, hex_to_bin};
+
+/// A place to get and put blocks
+pub trait Blockstore {
+    /// Returns the raw bytes of the block with the given hash, without checking them
+    ///
+    /// If the block isn't in the store, this returns `Error::MissingBlock`.  Only use this
+    /// directly when the store is trusted; otherwise use `get`.
+    fn get_unverified<M: Multihash>(&self, hash: M) -> Result<Vec<u8>>;
+
+    /// Returns the raw bytes of the block with the given hash
+    ///
+    /// The bytes are rehashed with the hash function named in `hash`, and
+    /// `Error::HashMismatch` is returned if they don't match.
+    fn get<M: Multihash>(&self, hash: M) -> Result<Vec<u8>> {
+        let data = try!(self.get_unverified(&hash));
+        try!(verify_block(&hash, &data));
+        Ok(data)
+    }
+
+    /// Stores a block under the given hash, replacing anything already stored there
+    fn put<M: Multihash>(&mut self, hash: M, data: &[u8]) -> Result<()>;
+
+    /// Returns true if a block with this hash is in the store
+    fn has<M: Multihash>(&self, hash: M) -> bool;
+
+    /// Removes the block with the given hash
+    fn delete<M: Multihash>(&mut self, hash: M) -> Result<()>;
+
+    /// Lists the hashes of every block in the store
+    fn iter_keys<'a>(&'a self) -> Box<Iterator<Item=MultihashBytes> + 'a>;
+}
+
+/// Checks that `data` hashes to `hash`, using the hash function encoded in `hash`
+pub fn verify_block<M: Multihash>(hash: M, data: &[u8]) -> Result<()> {
+    let expected = try!(ParsedMultihash::from_multihash(&hash));
+    let hash_type = try!(expected.hash_type().ok_or_else(|| {
+        Error::Multihash(format!("unknown hash function 0x{:02x}", expected.code()))
+    }));
+
+    let actual = try!(ParsedMultihash::from_bytes(&try!(hash_bytes(hash_type, data.to_vec()))));
+    if actual != expected {
+        return Err(Error::HashMismatch(expected.to_string(), actual.to_string()));
+    }
+    Ok(())
+}
+
+/// Returns the path to the local ipfs repo, from $IPFS_PATH or ~/.ipfs
+pub fn get_ipfs_path() -> PathBuf {
+    use std::env;
+
+    env::var("IPFS_PATH").and_then(|p| Ok(PathBuf::from(p))).unwrap_or_else(|_| { env::home_dir().unwrap().join(".ipfs")})
+}
+
+/// The flat-file layout used by go-ipfs
+///
+/// Each block lives in `<root>/<first 8 hex chars>/<full hex>.data`
+pub struct FsBlockstore {
+    root: PathBuf,
+}
+
+impl FsBlockstore {
+    /// A store rooted at the given blocks directory
+    pub fn new<P: Into<PathBuf>>(root: P) -> FsBlockstore {
+        FsBlockstore{ root: root.into() }
+    }
+
+    /// A store pointing at the `blocks` directory of the local ipfs repo
+    pub fn from_env() -> FsBlockstore {
+        FsBlockstore::new(get_ipfs_path().join("blocks"))
+    }
+
+    /// Returns the path of the file that does (or would) hold this block
+    pub fn path_for<M: Multihash>(&self, hash: M) -> Result<PathBuf> {
+        let hex = try!(hash.as_hex());
+        if hex.len() < 8 {
+            return Err(Error::Multihash(format!("{} is too short", hex)));
+        }
+        let mut path = self.root.clone();
+
+        path.push(&hex[0..8]);
+        path.push(&hex);
+        path.set_extension("data");
+
+        Ok(path)
+    }
+}
+
+impl Blockstore for FsBlockstore {
+    fn get_unverified<M: Multihash>(&self, hash: M) -> Result<Vec<u8>> {
+        let mut f = match ::std::fs::File::open(try!(self.path_for(&hash))) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Err(Error::MissingBlock(hash.base58())),
+            Err(e) => return Err(Error::Io(e))
+        };
+        let mut b = Vec::new();
+        try!(f.read_to_end(&mut b));
+        Ok(b)
+    }
+
+    fn put<M: Multihash>(&mut self, hash: M, data: &[u8]) -> Result<()> {
+        let path = try!(self.path_for(hash));
+        try!(::std::fs::create_dir_all(path.parent().unwrap()));
+
+        let mut f = try!(::std::fs::File::create(&path));
+        try!(f.write_all(data));
+        Ok(())
+    }
+
+    fn has<M: Multihash>(&self, hash: M) -> bool {
+        match self.path_for(hash) {
+            Ok(p) => ::std::fs::metadata(p).is_ok(),
+            Err(_) => false
+        }
+    }
+
+    fn delete<M: Multihash>(&mut self, hash: M) -> Result<()> {
+        match ::std::fs::remove_file(try!(self.path_for(&hash))) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(Error::MissingBlock(hash.base58())),
+            Err(e) => Err(Error::Io(e))
+        }
+    }
+
+    fn iter_keys<'a>(&'a self) -> Box<Iterator<Item=MultihashBytes> + 'a> {
+        let dirs = match ::std::fs::read_dir(&self.root) {
+            Ok(d) => d,
+            Err(_) => return Box::new(::std::iter::empty())
+        };
+
+        Box::new(dirs.filter_map(|e| e.ok())
+                 .filter_map(|e| ::std::fs::read_dir(e.path()).ok())
+                 .flat_map(|d| d)
+                 .filter_map(|e| e.ok())
+                 .filter_map(|e| {
+                     let path = e.path();
+                     if path.extension().and_then(|s| s.to_str()) != Some("data") {
+                         return None;
+                     }
+                     path.file_stem()
+                         .and_then(|s| s.to_str())
+                         .and_then(|hex| hex_to_bin(hex).ok())
+                         .map(MultihashBytes)
+                 }))
+    }
+}
+
+/// A store that keeps everything in memory, mostly useful for tests
+#[derive(Debug, Default)]
+pub struct MemBlockstore {
+    blocks: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemBlockstore {
+    pub fn new() -> MemBlockstore {
+        MemBlockstore{ blocks: HashMap::new() }
+    }
+
+    /// The number of blocks in the store
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+}
+
+impl Blockstore for MemBlockstore {
+    fn get_unverified<M: Multihash>(&self, hash: M) -> Result<Vec<u8>> {
+        let key = try!(hash.as_bytes());
+        self.blocks.get(&key).cloned().ok_or_else(|| Error::MissingBlock(hash.base58()))
+    }
+
+    fn put<M: Multihash>(&mut self, hash: M, data: &[u8]) -> Result<()> {
+        self.blocks.insert(try!(hash.as_bytes()), data.to_vec());
+        Ok(())
+    }
+
+    fn has<M: Multihash>(&self, hash: M) -> bool {
+        match hash.as_bytes() {
+            Ok(key) => self.blocks.contains_key(&key),
+            Err(_) => false
+        }
+    }
+
+    fn delete<M: Multihash>(&mut self, hash: M) -> Result<()> {
+        let key = try!(hash.as_bytes());
+        match self.blocks.remove(&key) {
+            Some(_) => Ok(()),
+            None => Err(Error::MissingBlock(hash.base58()))
+        }
+    }
+
+    fn iter_keys<'a>(&'a self) -> Box<Iterator<Item=MultihashBytes> + 'a> {
+        Box::new(self.blocks.keys().map(|k| MultihashBytes(k.clone())))
+    }
+}
+
+
+#[test]
+fn test_mem_blockstore() {
+    use multihash::MultihashStr;
+
+    let mut store = MemBlockstore::new();
+    let mh = MultihashStr("QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe".to_owned());
+    assert!(!store.has(&mh));
+    match store.get_unverified(&mh) {
+        Err(Error::MissingBlock(ref s)) => assert_eq!(s, "QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe"),
+        other => panic!("unexpected result {:?}", other)
+    }
+
+    store.put(&mh, b"hello").unwrap();
+    assert!(store.has(&mh));
+    assert_eq!(store.get_unverified(&mh).unwrap(), b"hello".to_vec());
+    assert_eq!(store.iter_keys().map(|k| k.as_hex().unwrap()).collect::<Vec<_>>(),
+               vec!["122070286b9afa6620a66f715c7020d68af3d10e1a497971629c07606bfdb812303d".to_owned()]);
+
+    store.delete(&mh).unwrap();
+    assert!(!store.has(&mh));
+    assert_eq!(store.len(), 0);
+}
+
+#[test]
+fn test_fs_blockstore() {
+    use multihash::MultihashStr;
+
+    let root = ::test_dir("fs_blockstore");
+    let mut store = FsBlockstore::new(root.clone());
+
+    let mh = MultihashStr("QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe".to_owned());
+    assert_eq!(store.path_for(&mh).unwrap(),
+               root.join("12207028").join("122070286b9afa6620a66f715c7020d68af3d10e1a497971629c07606bfdb812303d.data"));
+
+    store.put(&mh, b"hello").unwrap();
+    assert!(store.has(&mh));
+    assert_eq!(store.get_unverified(&mh).unwrap(), b"hello".to_vec());
+    assert_eq!(store.iter_keys().map(|k| k.base58()).collect::<Vec<_>>(),
+               vec!["QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe".to_owned()]);
+
+    store.delete(&mh).unwrap();
+    assert!(!store.has(&mh));
+    ::std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_verified_get() {
+    use rust_multihash::HashTypes;
+
+    let mut store = MemBlockstore::new();
+    let good = MultihashBytes(hash_bytes(HashTypes::SHA2256, b"hello".to_vec()).unwrap());
+    let bad = MultihashBytes(hash_bytes(HashTypes::SHA2256, b"world".to_vec()).unwrap());
+    let sha512 = MultihashBytes(hash_bytes(HashTypes::SHA2512, b"hello".to_vec()).unwrap());
+
+    store.put(&good, b"hello").unwrap();
+    store.put(&bad, b"hello").unwrap();
+    store.put(&sha512, b"hello").unwrap();
+
+    assert_eq!(store.get(&good).unwrap(), b"hello".to_vec());
+    assert_eq!(store.get(&sha512).unwrap(), b"hello".to_vec());
+    match store.get(&bad) {
+        Err(Error::HashMismatch(ref expected, ref actual)) => {
+            assert_eq!(*expected, bad.base58());
+            assert_eq!(*actual, good.base58());
+        }
+        other => panic!("unexpected result {:?}", other)
+    }
+    assert_eq!(store.get_unverified(&bad).unwrap(), b"hello".to_vec());
+}
@@ -0,0 +1,210 @@
+//! Content identifiers
+//!
+//! A CIDv0 is just a base58 sha2-256 multihash (`Qm...`), which always refers to a dag-pb node.
+//! A CIDv1 is `<version><codec><multihash>` (the first two as varints), written as a string with
+//! a multibase prefix (lowercase base32 by default).
+
+use std::fmt;
+use std::str::FromStr;
+
+use rust_multihash::HashTypes;
+
+use error::{Error, Result};
+use multibase;
+use multibase::Base;
+use multihash::{Multihash, ParsedMultihash};
+use varint::{encode_varint, decode_varint};
+use ::bin_to_hex;
+
+/// The format of the data a CID points to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Codec {
+    /// A merkledag::PBNode
+    DagProtobuf,
+    /// Raw bytes with no framing
+    Raw,
+    /// CBOR encoded IPLD
+    DagCbor,
+    /// Any other multicodec
+    Other(u64),
+}
+
+impl Codec {
+    pub fn from_code(code: u64) -> Codec {
+        match code {
+            0x70 => Codec::DagProtobuf,
+            0x55 => Codec::Raw,
+            0x71 => Codec::DagCbor,
+            c => Codec::Other(c)
+        }
+    }
+
+    pub fn code(&self) -> u64 {
+        match *self {
+            Codec::DagProtobuf => 0x70,
+            Codec::Raw => 0x55,
+            Codec::DagCbor => 0x71,
+            Codec::Other(c) => c
+        }
+    }
+}
+
+/// A version 0 or version 1 content identifier
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cid {
+    version: u64,
+    codec: Codec,
+    hash: ParsedMultihash,
+}
+
+impl Cid {
+    /// Makes a CIDv0, which must be a 32 byte sha2-256 hash
+    pub fn new_v0(hash: ParsedMultihash) -> Result<Cid> {
+        if hash.code() != HashTypes::SHA2256.to_u8() || hash.length() != 32 {
+            return Err(Error::Cid(format!("CIDv0 requires a sha2-256 multihash, not {}", hash)));
+        }
+        Ok(Cid{ version: 0, codec: Codec::DagProtobuf, hash: hash })
+    }
+
+    /// Makes a CIDv1
+    pub fn new_v1(codec: Codec, hash: ParsedMultihash) -> Cid {
+        Cid{ version: 1, codec: codec, hash: hash }
+    }
+
+    /// Parses the binary form of a CID
+    pub fn from_bytes(bytes: &[u8]) -> Result<Cid> {
+        if bytes.len() == 34 && bytes[0] == 0x12 && bytes[1] == 0x20 {
+            return Cid::new_v0(try!(ParsedMultihash::from_bytes(bytes)));
+        }
+
+        let (version, n) = try!(decode_varint(bytes));
+        if version != 1 {
+            return Err(Error::Cid(format!("unsupported CID version {}", version)));
+        }
+        let (codec, m) = try!(decode_varint(&bytes[n..]));
+        let hash = try!(ParsedMultihash::from_bytes(&bytes[n + m..]));
+
+        Ok(Cid::new_v1(Codec::from_code(codec), hash))
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub fn hash(&self) -> &ParsedMultihash {
+        &self.hash
+    }
+
+    /// Returns the CIDv1 that points to the same data
+    pub fn to_v1(&self) -> Cid {
+        Cid::new_v1(self.codec, self.hash.clone())
+    }
+
+    /// The binary form of this CID
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if self.version == 0 {
+            return self.hash.to_bytes();
+        }
+
+        let mut v = Vec::new();
+        encode_varint(self.version, &mut v);
+        encode_varint(self.codec.code(), &mut v);
+        v.extend(self.hash.to_bytes());
+        v
+    }
+
+    /// The string form of this CID, using the given multibase for CIDv1
+    ///
+    /// CIDv0 is always plain base58btc, with no multibase prefix.
+    pub fn to_string_of_base(&self, base: Base) -> String {
+        if self.version == 0 {
+            self.hash.to_string()
+        } else {
+            multibase::encode(base, &self.to_bytes())
+        }
+    }
+}
+
+impl FromStr for Cid {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Cid> {
+        if s.len() == 46 && s.starts_with("Qm") {
+            return Cid::new_v0(try!(s.parse()));
+        }
+
+        let (_, bytes) = try!(multibase::decode(s));
+        Cid::from_bytes(&bytes)
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_string_of_base(Base::Base32))
+    }
+}
+
+impl fmt::Debug for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cid({})", self)
+    }
+}
+
+/// Blocks are stored by multihash, so a CID can be used anywhere a multihash can
+impl Multihash for Cid {
+    fn base58(&self) -> String {
+        self.hash.base58()
+    }
+    fn as_bytes(&self) -> Result<Vec<u8>> {
+        Ok(self.hash.to_bytes())
+    }
+    fn as_hex(&self) -> Result<String> {
+        Ok(bin_to_hex(&self.hash.to_bytes()))
+    }
+}
+
+
+#[test]
+fn test_cid_v0() {
+    let s = "QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe";
+    let cid: Cid = s.parse().unwrap();
+    assert_eq!(cid.version(), 0);
+    assert_eq!(cid.codec(), Codec::DagProtobuf);
+    assert_eq!(cid.hash().to_string(), s);
+    assert_eq!(cid.to_string(), s);
+    assert_eq!(Cid::from_bytes(&cid.to_bytes()).unwrap(), cid);
+    assert_eq!(cid.as_hex().unwrap(), "122070286b9afa6620a66f715c7020d68af3d10e1a497971629c07606bfdb812303d");
+}
+
+#[test]
+fn test_cid_v1() {
+    let v0: Cid = "QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe".parse().unwrap();
+    let v1 = v0.to_v1();
+    assert_eq!(v1.version(), 1);
+    assert_eq!(v1.codec(), Codec::DagProtobuf);
+    assert_eq!(v1.hash(), v0.hash());
+    assert_eq!(v1.to_string(), "bafybeidqfbvzv6tgectg64k4oaqnncxt2ehbuslzofrjyb3anp63qerqhu");
+    assert_eq!(v1.to_string_of_base(Base::Base58Btc), "zdj7WcyiVKmicaGEPs4HBWnThXGbvrGpiA6Ex2NeJPdHKcjfa");
+    assert_eq!("bafybeidqfbvzv6tgectg64k4oaqnncxt2ehbuslzofrjyb3anp63qerqhu".parse::<Cid>().unwrap(), v1);
+    assert_eq!("zdj7WcyiVKmicaGEPs4HBWnThXGbvrGpiA6Ex2NeJPdHKcjfa".parse::<Cid>().unwrap(), v1);
+    assert_eq!(Cid::from_bytes(&v1.to_bytes()).unwrap(), v1);
+
+    let raw: Cid = "zb2rheC6bwv6zMgJsQgXYKTHE5AjzNKpf4hHQQvHAtYEA21hN".parse().unwrap();
+    assert_eq!(raw.codec(), Codec::Raw);
+    assert_eq!(raw.base58(), v0.base58());
+}
+
+#[test]
+fn test_cid_errors() {
+    assert!("".parse::<Cid>().is_err());
+    assert!("Qm".parse::<Cid>().is_err());
+    assert!("?abc".parse::<Cid>().is_err());
+    // version 2 doesn't exist
+    assert!(Cid::from_bytes(&[2, 0x70, 0x12, 0x00]).is_err());
+    // CIDv0 must be sha2-256
+    assert!(Cid::new_v0(ParsedMultihash::new(0x13, vec![0; 64]).unwrap()).is_err());
+}
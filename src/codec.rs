@@ -0,0 +1,161 @@
+//! Varint length-prefixed framing for protobuf messages
+//!
+//! Every frame is the length of the payload as a varint, followed by the payload.  This is how
+//! DHT peers exchange `dht::Message`s over a stream.
+
+use std::io;
+use std::io::{Read, Write};
+
+use protobuf;
+use protobuf::core::{Message, MessageStatic};
+
+use dht;
+use error::{Error, Result};
+use varint::{read_varint, write_varint};
+
+/// The largest frame we accept by default
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Reads one frame, returning `None` if the stream ended cleanly before it started
+pub fn read_frame<R: Read>(r: &mut R, max_size: usize) -> Result<Option<Vec<u8>>> {
+    let len = match read_varint(r) {
+        Ok(Some(len)) => len,
+        Ok(None) => return Ok(None),
+        Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            return Err(Error::TruncatedFrame("stream ended inside the length prefix".to_owned()));
+        }
+        Err(e) => return Err(e)
+    };
+    if len > max_size as u64 {
+        return Err(Error::FrameTooLarge(len, max_size));
+    }
+
+    let len = len as usize;
+    let mut buf = vec![0; len];
+    let mut filled = 0;
+    while filled < len {
+        let n = try!(r.read(&mut buf[filled..]));
+        if n == 0 {
+            return Err(Error::TruncatedFrame(format!("expected {} bytes but the stream ended after {}", len, filled)));
+        }
+        filled += n;
+    }
+    Ok(Some(buf))
+}
+
+/// Writes one frame
+pub fn write_frame<W: Write>(w: &mut W, data: &[u8], max_size: usize) -> Result<()> {
+    if data.len() > max_size {
+        return Err(Error::FrameTooLarge(data.len() as u64, max_size));
+    }
+    try!(write_varint(w, data.len() as u64));
+    try!(w.write_all(data));
+    try!(w.flush());
+    Ok(())
+}
+
+/// Reads one length-prefixed protobuf message
+pub fn read_message<R: Read, M: MessageStatic>(r: &mut R, max_size: usize) -> Result<Option<M>> {
+    match try!(read_frame(r, max_size)) {
+        Some(bytes) => Ok(Some(try!(protobuf::parse_from_bytes(&bytes)))),
+        None => Ok(None)
+    }
+}
+
+/// Writes one length-prefixed protobuf message
+pub fn write_message<W: Write, M: Message>(w: &mut W, msg: &M, max_size: usize) -> Result<()> {
+    let bytes = try!(msg.write_to_bytes());
+    write_frame(w, &bytes, max_size)
+}
+
+/// Sends and receives `dht::Message`s over a stream
+pub struct MessageCodec<S> {
+    stream: S,
+    max_size: usize,
+}
+
+impl<S: Read + Write> MessageCodec<S> {
+    pub fn new(stream: S) -> MessageCodec<S> {
+        MessageCodec::with_max_size(stream, DEFAULT_MAX_MESSAGE_SIZE)
+    }
+
+    pub fn with_max_size(stream: S, max_size: usize) -> MessageCodec<S> {
+        MessageCodec{ stream: stream, max_size: max_size }
+    }
+
+    /// Reads the next message, returning `None` if the other side has closed the stream
+    pub fn read(&mut self) -> Result<Option<dht::Message>> {
+        read_message(&mut self.stream, self.max_size)
+    }
+
+    pub fn write(&mut self, msg: &dht::Message) -> Result<()> {
+        write_message(&mut self.stream, msg, self.max_size)
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+
+#[test]
+fn test_message_roundtrip() {
+    use std::io::Cursor;
+
+    let mut ping = dht::Message::new();
+    ping.set_field_type(dht::Message_MessageType::PING);
+    let mut find = dht::Message::new();
+    find.set_field_type(dht::Message_MessageType::FIND_NODE);
+    find.set_key("some key".to_owned());
+
+    let mut codec = MessageCodec::new(Cursor::new(Vec::new()));
+    codec.write(&ping).unwrap();
+    codec.write(&find).unwrap();
+
+    let mut codec = MessageCodec::new(Cursor::new(codec.into_inner().into_inner()));
+    assert_eq!(codec.read().unwrap(), Some(ping));
+    assert_eq!(codec.read().unwrap(), Some(find));
+    assert_eq!(codec.read().unwrap(), None);
+}
+
+#[test]
+fn test_oversized_frames() {
+    let mut buf = Vec::new();
+    match write_frame(&mut buf, &[0; 100], 10) {
+        Err(Error::FrameTooLarge(100, 10)) => {}
+        other => panic!("unexpected result {:?}", other)
+    }
+    assert!(buf.is_empty());
+
+    write_frame(&mut buf, &[0; 100], 1000).unwrap();
+    match read_frame(&mut &buf[..], 10) {
+        Err(Error::FrameTooLarge(100, 10)) => {}
+        other => panic!("unexpected result {:?}", other)
+    }
+}
+
+#[test]
+fn test_truncated_frames() {
+    let mut buf = Vec::new();
+    write_frame(&mut buf, b"hello", 1000).unwrap();
+
+    // cut off in the middle of the payload
+    match read_frame(&mut &buf[..4], 1000) {
+        Err(Error::TruncatedFrame(ref s)) => assert_eq!(s, "expected 5 bytes but the stream ended after 3"),
+        other => panic!("unexpected result {:?}", other)
+    }
+
+    // cut off in the middle of the length prefix
+    match read_frame(&mut &[0x80u8][..], 1000) {
+        Err(Error::TruncatedFrame(_)) => {}
+        other => panic!("unexpected result {:?}", other)
+    }
+}
@@ -0,0 +1,459 @@
+//! The go-ipfs repo config file, `$IPFS_PATH/config`
+//!
+//! Only the sections we use are modelled.  Everything else, at the top level or inside those
+//! sections, is kept as raw JSON in `extra` so that saving a config doesn't lose anything go-ipfs
+//! put there.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use protobuf::core::Message;
+use rustc_serialize::base64;
+use rustc_serialize::base64::{FromBase64, ToBase64};
+use rustc_serialize::json;
+use rustc_serialize::json::{Json, ToJson};
+
+use crypto::{KeyType, PrivateKey};
+use error::{Error, Result};
+use keys;
+use peer::PeerId;
+
+/// The bootstrap peers that go-ipfs ships with
+pub const DEFAULT_BOOTSTRAP: [&'static str; 9] = [
+    "/ip4/104.131.131.82/tcp/4001/ipfs/QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ",
+    "/ip4/104.236.176.52/tcp/4001/ipfs/QmSoLnSGccFuZQJzRadHn95W2CrSFmZuTdDWP8HXaHca9z",
+    "/ip4/104.236.179.241/tcp/4001/ipfs/QmSoLPppuBtQSGwKDZT2M73ULpjvfd3aZ6ha4oFGL1KrGM",
+    "/ip4/162.243.248.213/tcp/4001/ipfs/QmSoLueR4xBeUbY9WZ9xGUUxunbKWcrNFTDAadQJmocnWm",
+    "/ip4/128.199.219.111/tcp/4001/ipfs/QmSoLSafTMBsPKadTEgaXctDQVcqN88CNLHXMkTNwMKPnu",
+    "/ip4/104.236.76.40/tcp/4001/ipfs/QmSoLV4Bbm51jM9C4gDYZQ9Cy3U6aXMJDAbzgu2fzaDs64",
+    "/ip4/178.62.158.247/tcp/4001/ipfs/QmSoLer265NRgSp2LA3dPaeykiS1J6DifTC88f5uVQKNAd",
+    "/ip4/178.62.61.185/tcp/4001/ipfs/QmSoLMeWqB7YGVLJN3pNLQpmmEk35v6wYtsMGLzSr5QBU3",
+    "/ip4/104.236.151.122/tcp/4001/ipfs/QmSoLju6m7xTh3DuokvT3886QRYqxAzb1kShaanJgW36yx",
+];
+
+/// The size of RSA keys generated by go-ipfs
+pub const DEFAULT_KEY_BITS: usize = 2048;
+
+/// `Identity`: who this node is
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identity {
+    /// The base58 peer ID
+    pub peer_id: String,
+    /// The base64 encoded `crypto::PrivateKey`
+    pub priv_key: String,
+    pub extra: BTreeMap<String, Json>,
+}
+
+/// `Datastore`: where and how go-ipfs keeps its data
+#[derive(Clone, Debug, PartialEq)]
+pub struct Datastore {
+    pub kind: String,
+    pub path: String,
+    pub storage_max: String,
+    pub storage_gc_watermark: i64,
+    pub gc_period: String,
+    pub no_sync: bool,
+    pub extra: BTreeMap<String, Json>,
+}
+
+/// `Addresses`: the multiaddrs to listen on
+#[derive(Clone, Debug, PartialEq)]
+pub struct Addresses {
+    pub swarm: Vec<String>,
+    pub api: String,
+    pub gateway: String,
+    pub extra: BTreeMap<String, Json>,
+}
+
+/// The whole config file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub identity: Identity,
+    pub datastore: Datastore,
+    pub addresses: Addresses,
+    pub bootstrap: Vec<String>,
+    /// Every other top level section
+    pub extra: BTreeMap<String, Json>,
+}
+
+fn take_object(obj: &mut json::Object, key: &str) -> Result<json::Object> {
+    match obj.remove(key) {
+        Some(Json::Object(o)) => Ok(o),
+        Some(Json::Null) | None => Ok(json::Object::new()),
+        Some(_) => Err(Error::Config(format!("{} should be an object", key)))
+    }
+}
+
+fn take_string(obj: &mut json::Object, section: &str, key: &str, default: Option<&str>) -> Result<String> {
+    match obj.remove(key) {
+        Some(Json::String(s)) => Ok(s),
+        Some(Json::Null) | None => match default {
+            Some(d) => Ok(d.to_owned()),
+            None => Err(Error::Config(format!("{}.{} is missing", section, key)))
+        },
+        Some(_) => Err(Error::Config(format!("{}.{} should be a string", section, key)))
+    }
+}
+
+fn take_i64(obj: &mut json::Object, section: &str, key: &str, default: i64) -> Result<i64> {
+    match obj.remove(key) {
+        Some(Json::I64(n)) => Ok(n),
+        Some(Json::U64(n)) => Ok(n as i64),
+        Some(Json::Null) | None => Ok(default),
+        Some(_) => Err(Error::Config(format!("{}.{} should be an integer", section, key)))
+    }
+}
+
+fn take_bool(obj: &mut json::Object, section: &str, key: &str, default: bool) -> Result<bool> {
+    match obj.remove(key) {
+        Some(Json::Boolean(b)) => Ok(b),
+        Some(Json::Null) | None => Ok(default),
+        Some(_) => Err(Error::Config(format!("{}.{} should be a boolean", section, key)))
+    }
+}
+
+fn take_strings(obj: &mut json::Object, name: &str, key: &str) -> Result<Vec<String>> {
+    match obj.remove(key) {
+        Some(Json::Array(items)) => {
+            items.into_iter().map(|item| match item {
+                Json::String(s) => Ok(s),
+                _ => Err(Error::Config(format!("{} should only contain strings", name)))
+            }).collect()
+        }
+        Some(Json::Null) | None => Ok(Vec::new()),
+        Some(_) => Err(Error::Config(format!("{} should be an array", name)))
+    }
+}
+
+fn strings_to_json(v: &[String]) -> Json {
+    Json::Array(v.iter().map(|s| Json::String(s.clone())).collect())
+}
+
+impl Identity {
+    fn from_json(mut obj: json::Object) -> Result<Identity> {
+        Ok(Identity{
+            peer_id: try!(take_string(&mut obj, "Identity", "PeerID", None)),
+            priv_key: try!(take_string(&mut obj, "Identity", "PrivKey", None)),
+            extra: obj,
+        })
+    }
+
+    /// An identity for a private key
+    pub fn from_private_key(key: &PrivateKey) -> Result<Identity> {
+        let bytes = try!(key.write_to_bytes());
+        Ok(Identity{
+            peer_id: try!(PeerId::from_private_key(key)).to_base58(),
+            priv_key: bytes.to_base64(base64::STANDARD),
+            extra: BTreeMap::new(),
+        })
+    }
+
+    pub fn peer_id(&self) -> Result<PeerId> {
+        self.peer_id.parse()
+    }
+
+    /// Decodes `PrivKey`
+    pub fn private_key(&self) -> Result<PrivateKey> {
+        let bytes = try!(self.priv_key.from_base64());
+        let mut key = PrivateKey::new();
+        try!(key.merge_from_bytes(&bytes).map_err(|e| Error::KeyParse(format!("{:?}", e))));
+        Ok(key)
+    }
+
+    /// Checks that `PeerID` is the ID of `PrivKey`
+    pub fn validate(&self) -> Result<()> {
+        let expected = try!(self.peer_id());
+        let actual = try!(PeerId::from_private_key(&try!(self.private_key())));
+        if actual != expected {
+            return Err(Error::PeerIdMismatch(expected.to_base58(), actual.to_base58()));
+        }
+        Ok(())
+    }
+}
+
+impl ToJson for Identity {
+    fn to_json(&self) -> Json {
+        let mut obj = self.extra.clone();
+        obj.insert("PeerID".to_owned(), Json::String(self.peer_id.clone()));
+        obj.insert("PrivKey".to_owned(), Json::String(self.priv_key.clone()));
+        Json::Object(obj)
+    }
+}
+
+impl Datastore {
+    fn from_json(mut obj: json::Object) -> Result<Datastore> {
+        Ok(Datastore{
+            kind: try!(take_string(&mut obj, "Datastore", "Type", Some("leveldb"))),
+            path: try!(take_string(&mut obj, "Datastore", "Path", Some(""))),
+            storage_max: try!(take_string(&mut obj, "Datastore", "StorageMax", Some("10GB"))),
+            storage_gc_watermark: try!(take_i64(&mut obj, "Datastore", "StorageGCWatermark", 90)),
+            gc_period: try!(take_string(&mut obj, "Datastore", "GCPeriod", Some("1h"))),
+            no_sync: try!(take_bool(&mut obj, "Datastore", "NoSync", false)),
+            extra: obj,
+        })
+    }
+
+    /// The go-ipfs defaults, for a repo at `repo`
+    pub fn default_for(repo: &Path) -> Datastore {
+        Datastore{
+            kind: "leveldb".to_owned(),
+            path: repo.join("datastore").to_string_lossy().into_owned(),
+            storage_max: "10GB".to_owned(),
+            storage_gc_watermark: 90,
+            gc_period: "1h".to_owned(),
+            no_sync: false,
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+impl ToJson for Datastore {
+    fn to_json(&self) -> Json {
+        let mut obj = self.extra.clone();
+        obj.insert("Type".to_owned(), Json::String(self.kind.clone()));
+        obj.insert("Path".to_owned(), Json::String(self.path.clone()));
+        obj.insert("StorageMax".to_owned(), Json::String(self.storage_max.clone()));
+        obj.insert("StorageGCWatermark".to_owned(), Json::I64(self.storage_gc_watermark));
+        obj.insert("GCPeriod".to_owned(), Json::String(self.gc_period.clone()));
+        obj.insert("NoSync".to_owned(), Json::Boolean(self.no_sync));
+        Json::Object(obj)
+    }
+}
+
+impl Addresses {
+    fn from_json(mut obj: json::Object) -> Result<Addresses> {
+        Ok(Addresses{
+            swarm: try!(take_strings(&mut obj, "Addresses.Swarm", "Swarm")),
+            api: try!(take_string(&mut obj, "Addresses", "API", Some(""))),
+            gateway: try!(take_string(&mut obj, "Addresses", "Gateway", Some(""))),
+            extra: obj,
+        })
+    }
+}
+
+impl Default for Addresses {
+    /// The go-ipfs defaults
+    fn default() -> Addresses {
+        Addresses{
+            swarm: vec!["/ip4/0.0.0.0/tcp/4001".to_owned(), "/ip6/::/tcp/4001".to_owned()],
+            api: "/ip4/127.0.0.1/tcp/5001".to_owned(),
+            gateway: "/ip4/127.0.0.1/tcp/8080".to_owned(),
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+impl ToJson for Addresses {
+    fn to_json(&self) -> Json {
+        let mut obj = self.extra.clone();
+        obj.insert("Swarm".to_owned(), strings_to_json(&self.swarm));
+        obj.insert("API".to_owned(), Json::String(self.api.clone()));
+        obj.insert("Gateway".to_owned(), Json::String(self.gateway.clone()));
+        Json::Object(obj)
+    }
+}
+
+impl Config {
+    pub fn from_json(json: Json) -> Result<Config> {
+        let mut obj = match json {
+            Json::Object(o) => o,
+            _ => return Err(Error::Config("the config should be a JSON object".to_owned()))
+        };
+        if !obj.contains_key("Identity") {
+            return Err(Error::Config("Identity is missing".to_owned()));
+        }
+        Ok(Config{
+            identity: try!(Identity::from_json(try!(take_object(&mut obj, "Identity")))),
+            datastore: try!(Datastore::from_json(try!(take_object(&mut obj, "Datastore")))),
+            addresses: try!(Addresses::from_json(try!(take_object(&mut obj, "Addresses")))),
+            bootstrap: try!(take_strings(&mut obj, "Bootstrap", "Bootstrap")),
+            extra: obj,
+        })
+    }
+
+    /// The default config for a new repo at `repo`, with the given key
+    pub fn new(repo: &Path, key: &PrivateKey) -> Result<Config> {
+        Ok(Config{
+            identity: try!(Identity::from_private_key(key)),
+            datastore: Datastore::default_for(repo),
+            addresses: Addresses::default(),
+            bootstrap: DEFAULT_BOOTSTRAP.iter().map(|s| (*s).to_owned()).collect(),
+            extra: BTreeMap::new(),
+        })
+    }
+
+    /// Reads and validates a config file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config> {
+        let mut s = String::new();
+        try!(try!(fs::File::open(path)).read_to_string(&mut s));
+        s.parse()
+    }
+
+    /// Reads the config of the repo at `repo`
+    pub fn load_repo<P: AsRef<Path>>(repo: P) -> Result<Config> {
+        Config::load(repo.as_ref().join("config"))
+    }
+
+    pub fn to_string_pretty(&self) -> String {
+        format!("{}", json::as_pretty_json(&self.to_json()).indent(2))
+    }
+
+    /// Writes the config, readable only by its owner since it holds the private key
+    ///
+    /// The new contents are written to a temporary file which is then renamed over `path`, so a
+    /// crash part way through doesn't leave a truncated config.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        {
+            let mut f = try!(create_private(&tmp));
+            try!(f.write_all(self.to_string_pretty().as_bytes()));
+            try!(f.write_all(b"\n"));
+        }
+        try!(fs::rename(&tmp, path));
+        Ok(())
+    }
+}
+
+impl FromStr for Config {
+    type Err = Error;
+
+    /// Parses a config, checking that the identity is consistent
+    fn from_str(s: &str) -> Result<Config> {
+        let config = try!(Config::from_json(try!(Json::from_str(s))));
+        try!(config.identity.validate());
+        Ok(config)
+    }
+}
+
+impl ToJson for Config {
+    fn to_json(&self) -> Json {
+        let mut obj = self.extra.clone();
+        obj.insert("Identity".to_owned(), self.identity.to_json());
+        obj.insert("Datastore".to_owned(), self.datastore.to_json());
+        obj.insert("Addresses".to_owned(), self.addresses.to_json());
+        obj.insert("Bootstrap".to_owned(), strings_to_json(&self.bootstrap));
+        Json::Object(obj)
+    }
+}
+
+#[cfg(unix)]
+fn create_private(path: &Path) -> Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    Ok(try!(fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)))
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> Result<fs::File> {
+    Ok(try!(fs::OpenOptions::new().write(true).create(true).truncate(true).open(path)))
+}
+
+/// Creates a new repo at `repo`, with a freshly generated key
+///
+/// `bits` is only used for RSA keys.
+/// This makes the `blocks`, `datastore` and `keystore` directories and writes a default config.
+/// It fails if there is already a config there.
+pub fn init<P: AsRef<Path>>(repo: P, key_type: KeyType, bits: usize) -> Result<Config> {
+    let repo = repo.as_ref();
+    let config_path = repo.join("config");
+    if fs::metadata(&config_path).is_ok() {
+        return Err(Error::Config(format!("{} already exists", config_path.display())));
+    }
+
+    for dir in &["blocks", "datastore", "keystore"] {
+        try!(fs::create_dir_all(repo.join(dir)));
+    }
+    let key = try!(keys::generate(key_type, bits));
+    let config = try!(Config::new(repo, &key));
+    try!(config.save(&config_path));
+    Ok(config)
+}
+
+/// The path of the config file of the local repo
+pub fn config_path() -> PathBuf {
+    ::blockstore::get_ipfs_path().join("config")
+}
+
+
+#[test]
+fn test_parse_config() {
+    let key = keys::generate_rsa(1024);
+    let identity = Identity::from_private_key(&key).unwrap();
+    let s = format!(r#"{{
+  "Identity": {{ "PeerID": "{}", "PrivKey": "{}" }},
+  "Datastore": {{ "Type": "leveldb", "Path": "/home/user/.ipfs/datastore", "StorageMax": "20GB",
+                 "StorageGCWatermark": 80, "GCPeriod": "2h", "Params": null, "NoSync": true }},
+  "Addresses": {{ "Swarm": ["/ip4/0.0.0.0/tcp/4002"], "API": "/ip4/127.0.0.1/tcp/5002", "Gateway": "" }},
+  "Bootstrap": ["/ip4/104.131.131.82/tcp/4001/ipfs/QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ"],
+  "Gateway": {{ "Writable": false }}
+}}"#, identity.peer_id, identity.priv_key);
+
+    let config = s.parse::<Config>().unwrap();
+    assert_eq!(config.identity, identity);
+    assert_eq!(config.identity.private_key().unwrap(), key);
+    assert_eq!(config.datastore.path, "/home/user/.ipfs/datastore");
+    assert_eq!(config.datastore.storage_max, "20GB");
+    assert_eq!(config.datastore.storage_gc_watermark, 80);
+    assert!(config.datastore.no_sync);
+    assert_eq!(config.datastore.extra.get("Params"), Some(&Json::Null));
+    assert_eq!(config.addresses.swarm, vec!["/ip4/0.0.0.0/tcp/4002".to_owned()]);
+    assert_eq!(config.addresses.api, "/ip4/127.0.0.1/tcp/5002");
+    assert_eq!(config.bootstrap.len(), 1);
+    assert!(config.extra.contains_key("Gateway"));
+
+    // unknown sections survive a round trip
+    let again = config.to_string_pretty().parse::<Config>().unwrap();
+    assert_eq!(again, config);
+}
+
+#[test]
+fn test_mismatched_identity() {
+    let identity = Identity::from_private_key(&keys::generate_rsa(1024)).unwrap();
+    let other = Identity::from_private_key(&keys::generate_rsa(1024)).unwrap();
+    let s = format!(r#"{{ "Identity": {{ "PeerID": "{}", "PrivKey": "{}" }} }}"#, other.peer_id, identity.priv_key);
+    match s.parse::<Config>() {
+        Err(Error::PeerIdMismatch(ref expected, ref actual)) => {
+            assert_eq!(expected, &other.peer_id);
+            assert_eq!(actual, &identity.peer_id);
+        }
+        other => panic!("unexpected result {:?}", other)
+    }
+
+    assert!(r#"{ "Bootstrap": [] }"#.parse::<Config>().is_err());
+    assert!(r#"{ "Identity": { "PeerID": 5 } }"#.parse::<Config>().is_err());
+    assert!("not json".parse::<Config>().is_err());
+}
+
+#[test]
+fn test_init() {
+    let repo = ::test_dir("init");
+
+    let config = init(&repo, KeyType::RSA, 1024).unwrap();
+    for dir in &["blocks", "datastore", "keystore"] {
+        assert!(fs::metadata(repo.join(dir)).unwrap().is_dir());
+    }
+    assert_eq!(config.bootstrap.len(), DEFAULT_BOOTSTRAP.len());
+    assert_eq!(config.datastore.path, repo.join("datastore").to_string_lossy());
+    assert_eq!(Config::load_repo(&repo).unwrap(), config);
+
+    match init(&repo, KeyType::Ed25519, 0) {
+        Err(Error::Config(_)) => {}
+        other => panic!("unexpected result {:?}", other)
+    }
+    fs::remove_dir_all(&repo).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_config_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let repo = ::test_dir("config_permissions");
+    init(&repo, KeyType::Ed25519, 0).unwrap();
+    assert_eq!(fs::metadata(repo.join("config")).unwrap().permissions().mode() & 0o777, 0o600);
+    fs::remove_dir_all(&repo).unwrap();
+}
@@ -0,0 +1,257 @@
+//! Keeps track of open connections, by peer
+//!
+//! The manager reuses an existing connection when one is open, enforces limits on the total
+//! number of connections and on connections per peer, and closes connections that have been
+//! idle for too long.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use error::{Error, Result};
+use multiaddr::Multiaddr;
+use peer::PeerId;
+use transport;
+use transport::Connection;
+
+/// Limits and timeouts for a `ConnectionManager`
+#[derive(Clone, Debug)]
+pub struct ConnectionConfig {
+    /// The most connections open at once, to all peers
+    pub max_connections: usize,
+    /// The most connections open to any one peer
+    pub max_per_peer: usize,
+    /// Connections unused for this long are closed by `prune_idle`
+    pub idle_timeout: Duration,
+    /// How long to wait when dialing and negotiating
+    pub dial_timeout: Duration,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> ConnectionConfig {
+        ConnectionConfig{
+            max_connections: 100,
+            max_per_peer: 1,
+            idle_timeout: Duration::from_secs(5 * 60),
+            dial_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Open connections, by peer ID
+pub struct ConnectionManager {
+    config: ConnectionConfig,
+    conns: HashMap<PeerId, Vec<Connection>>,
+}
+
+impl ConnectionManager {
+    pub fn new(config: ConnectionConfig) -> ConnectionManager {
+        ConnectionManager{ config: config, conns: HashMap::new() }
+    }
+
+    pub fn config(&self) -> &ConnectionConfig {
+        &self.config
+    }
+
+    /// The total number of open connections
+    pub fn len(&self) -> usize {
+        self.conns.values().map(|v| v.len()).sum()
+    }
+
+    /// The number of open connections to `peer`
+    pub fn connections_to(&self, peer: &PeerId) -> usize {
+        self.conns.get(peer).map(|v| v.len()).unwrap_or(0)
+    }
+
+    /// Every peer we have a connection to
+    pub fn peers(&self) -> Vec<PeerId> {
+        self.conns.keys().cloned().collect()
+    }
+
+    /// Checks that one more connection to `peer` is allowed, closing idle ones to make room
+    fn check_limits(&mut self, peer: &PeerId) -> Result<()> {
+        if self.connections_to(peer) >= self.config.max_per_peer {
+            return Err(Error::ConnectionLimit(format!("already have {} connections to {}", self.connections_to(peer), peer)));
+        }
+        if self.len() >= self.config.max_connections {
+            self.prune_idle();
+        }
+        if self.len() >= self.config.max_connections {
+            return Err(Error::ConnectionLimit(format!("already have {} connections", self.len())));
+        }
+        Ok(())
+    }
+
+    /// Starts tracking a connection to `peer`
+    ///
+    /// If that would go over a limit, the connection is closed instead.
+    pub fn add(&mut self, peer: PeerId, conn: Connection) -> Result<()> {
+        if let Err(e) = self.check_limits(&peer) {
+            let _ = conn.close();
+            return Err(e);
+        }
+        self.insert(peer, conn);
+        Ok(())
+    }
+
+    /// Tracks a connection that has already passed `check_limits`
+    fn insert(&mut self, peer: PeerId, conn: Connection) {
+        self.conns.entry(peer).or_insert_with(Vec::new).push(conn);
+    }
+
+    /// The most recently used connection to `peer`
+    pub fn get(&mut self, peer: &PeerId) -> Option<&mut Connection> {
+        self.conns.get_mut(peer).and_then(|v| v.iter_mut().max_by_key(|c| c.last_used()))
+    }
+
+    /// Returns an open connection to the peer at `addr`, dialing it if there isn't one
+    ///
+    /// `addr` must end with the peer's ID, like `/ip4/1.2.3.4/tcp/4001/ipfs/Qm...`.
+    pub fn dial(&mut self, addr: &Multiaddr, protocols: &[&str]) -> Result<&mut Connection> {
+        let peer = match addr.peer_id() {
+            Some(peer) => peer.clone(),
+            None => return Err(Error::Transport(format!("{} doesn't say which peer it belongs to", addr)))
+        };
+        if self.connections_to(&peer) == 0 {
+            // check before dialing, so we never open a connection just to throw it away
+            try!(self.check_limits(&peer));
+            let conn = try!(transport::dial(addr, protocols, self.config.dial_timeout));
+            self.insert(peer.clone(), conn);
+        }
+        Ok(self.get(&peer).unwrap())
+    }
+
+    /// Closes and forgets every connection to `peer`
+    pub fn remove(&mut self, peer: &PeerId) -> usize {
+        match self.conns.remove(peer) {
+            Some(conns) => {
+                for conn in &conns {
+                    let _ = conn.close();
+                }
+                conns.len()
+            }
+            None => 0
+        }
+    }
+
+    /// Closes connections that have been idle longer than the idle timeout
+    ///
+    /// Returns the peers that no longer have any connections.
+    pub fn prune_idle(&mut self) -> Vec<PeerId> {
+        self.prune_idle_at(Instant::now())
+    }
+
+    /// Like `prune_idle`, pretending that it's now `now`
+    pub fn prune_idle_at(&mut self, now: Instant) -> Vec<PeerId> {
+        let timeout = self.config.idle_timeout;
+        let mut gone = Vec::new();
+        for (peer, conns) in self.conns.iter_mut() {
+            conns.retain(|c| {
+                let idle = now.duration_since(c.last_used()) >= timeout;
+                if idle {
+                    let _ = c.close();
+                }
+                !idle
+            });
+            if conns.is_empty() {
+                gone.push(peer.clone());
+            }
+        }
+        for peer in &gone {
+            self.conns.remove(peer);
+        }
+        gone
+    }
+}
+
+
+#[cfg(test)]
+fn spawn_node(seed: u8) -> (Multiaddr, ::std::thread::JoinHandle<()>) {
+    use std::thread;
+    use codec::MessageCodec;
+    use keys;
+    use multiaddr::Protocol;
+    use multistream;
+    use transport::Listener;
+
+    let id = PeerId::from_private_key(&keys::ed25519_from_seed(&[seed; 32])).unwrap();
+    let listener = Listener::bind(&"/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+    let mut addr = listener.local_addr().clone();
+    addr.push(Protocol::Ipfs(id));
+
+    // answer every message on one connection with itself, like a PING
+    let t = thread::spawn(move || {
+        let mut protocols = multistream::Listener::new();
+        protocols.add("/ipfs/kad/1.0.0");
+        let conn = listener.accept(&protocols, Duration::from_secs(5)).unwrap();
+        let mut codec = MessageCodec::new(conn);
+        while let Some(msg) = codec.read().unwrap() {
+            codec.write(&msg).unwrap();
+        }
+    });
+    (addr, t)
+}
+
+#[test]
+fn test_dial_and_reuse() {
+    use codec::MessageCodec;
+    use dht::{Message, Message_MessageType};
+
+    let (addr, t) = spawn_node(1);
+    let peer = addr.peer_id().unwrap().clone();
+    let mut mgr = ConnectionManager::new(ConnectionConfig::default());
+
+    let mut ping = Message::new();
+    ping.set_field_type(Message_MessageType::PING);
+    for _ in 0..2 {
+        // the second time around reuses the first connection
+        let conn = mgr.dial(&addr, &["/ipfs/kad/1.0.0"]).unwrap();
+        assert_eq!(conn.remote_peer(), Some(&peer));
+        let mut codec = MessageCodec::new(conn);
+        codec.write(&ping).unwrap();
+        assert_eq!(codec.read().unwrap(), Some(ping.clone()));
+    }
+    assert_eq!(mgr.len(), 1);
+    assert_eq!(mgr.peers(), vec![peer.clone()]);
+
+    // without a peer ID we can't track the connection
+    assert!(mgr.dial(&addr.without_peer_id(), &["/ipfs/kad/1.0.0"]).is_err());
+
+    assert_eq!(mgr.remove(&peer), 1);
+    assert_eq!(mgr.len(), 0);
+    t.join().unwrap();
+}
+
+#[test]
+fn test_limits_and_idle_timeout() {
+    let (a, ta) = spawn_node(2);
+    let (b, tb) = spawn_node(3);
+    let mut mgr = ConnectionManager::new(ConnectionConfig{ max_connections: 1, idle_timeout: Duration::from_secs(60), ..ConnectionConfig::default() });
+
+    mgr.dial(&a, &["/ipfs/kad/1.0.0"]).unwrap();
+    match mgr.dial(&b, &["/ipfs/kad/1.0.0"]) {
+        Err(Error::ConnectionLimit(_)) => {}
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("the connection limit wasn't enforced")
+    }
+
+    // once the first connection has been idle long enough, it makes way for the second
+    let later = Instant::now() + Duration::from_secs(61);
+    assert_eq!(mgr.prune_idle_at(later), vec![a.peer_id().unwrap().clone()]);
+    assert_eq!(mgr.len(), 0);
+    mgr.dial(&b, &["/ipfs/kad/1.0.0"]).unwrap();
+    assert_eq!(mgr.connections_to(b.peer_id().unwrap()), 1);
+
+    // a connection added over the limit is closed, which lets c's thread finish
+    let (c, tc) = spawn_node(4);
+    let conn = transport::dial(&c, &["/ipfs/kad/1.0.0"], Duration::from_secs(5)).unwrap();
+    match mgr.add(c.peer_id().unwrap().clone(), conn) {
+        Err(Error::ConnectionLimit(_)) => {}
+        other => panic!("unexpected result {:?}", other)
+    }
+    tc.join().unwrap();
+    assert_eq!(mgr.len(), 1);
+
+    mgr.remove(b.peer_id().unwrap());
+    ta.join().unwrap();
+    tb.join().unwrap();
+}
@@ -477,6 +477,7 @@ impl ::std::fmt::Debug for PrivateKey {
 #[derive(Clone,PartialEq,Eq,Debug,Hash)]
 pub enum KeyType {
     RSA = 0,
+    Ed25519 = 1,
 }
 
 impl ::protobuf::ProtobufEnum for KeyType {
@@ -487,6 +488,7 @@ impl ::protobuf::ProtobufEnum for KeyType {
     fn from_i32(value: i32) -> ::std::option::Option<KeyType> {
         match value {
             0 => ::std::option::Option::Some(KeyType::RSA),
+            1 => ::std::option::Option::Some(KeyType::Ed25519),
             _ => ::std::option::Option::None
         }
     }
@@ -517,8 +519,9 @@ static file_descriptor_proto_data: &'static [u8] = &[
     0x65, 0x4b, 0x65, 0x79, 0x12, 0x20, 0x0a, 0x04, 0x54, 0x79, 0x70, 0x65, 0x18, 0x01, 0x20, 0x02,
     0x28, 0x0e, 0x32, 0x12, 0x2e, 0x63, 0x72, 0x79, 0x70, 0x74, 0x6f, 0x2e, 0x70, 0x62, 0x2e, 0x4b,
     0x65, 0x79, 0x54, 0x79, 0x70, 0x65, 0x12, 0x0c, 0x0a, 0x04, 0x44, 0x61, 0x74, 0x61, 0x18, 0x02,
-    0x20, 0x02, 0x28, 0x0c, 0x2a, 0x12, 0x0a, 0x07, 0x4b, 0x65, 0x79, 0x54, 0x79, 0x70, 0x65, 0x12,
-    0x07, 0x0a, 0x03, 0x52, 0x53, 0x41, 0x10, 0x00,
+    0x20, 0x02, 0x28, 0x0c, 0x2a, 0x1f, 0x0a, 0x07, 0x4b, 0x65, 0x79, 0x54, 0x79, 0x70, 0x65, 0x12,
+    0x07, 0x0a, 0x03, 0x52, 0x53, 0x41, 0x10, 0x00, 0x12, 0x0b, 0x0a, 0x07, 0x45, 0x64, 0x32, 0x35,
+    0x35, 0x31, 0x39, 0x10, 0x01,
 ];
 
 static mut file_descriptor_proto_lazy: ::protobuf::lazy::Lazy<::protobuf::descriptor::FileDescriptorProto> = ::protobuf::lazy::Lazy {
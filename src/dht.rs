@@ -780,7 +780,7 @@ pub struct Record {
     // message fields
     key: ::protobuf::SingularField<::std::string::String>,
     value: ::protobuf::SingularField<::std::vec::Vec<u8>>,
-    author: ::protobuf::SingularField<::std::string::String>,
+    author: ::protobuf::SingularField<::std::vec::Vec<u8>>,
     signature: ::protobuf::SingularField<::std::vec::Vec<u8>>,
     // special fields
     unknown_fields: ::protobuf::UnknownFields,
@@ -883,7 +883,7 @@ impl Record {
         }
     }
 
-    // optional string author = 3;
+    // optional bytes author = 3;
 
     pub fn clear_author(&mut self) {
         self.author.clear();
@@ -894,13 +894,13 @@ impl Record {
     }
 
     // Param is passed by value, moved
-    pub fn set_author(&mut self, v: ::std::string::String) {
+    pub fn set_author(&mut self, v: ::std::vec::Vec<u8>) {
         self.author = ::protobuf::SingularField::some(v);
     }
 
     // Mutable pointer to the field.
     // If field is not initialized, it is initialized with default value first.
-    pub fn mut_author<'a>(&'a mut self) -> &'a mut ::std::string::String {
+    pub fn mut_author<'a>(&'a mut self) -> &'a mut ::std::vec::Vec<u8> {
         if self.author.is_none() {
             self.author.set_default();
         };
@@ -908,14 +908,14 @@ impl Record {
     }
 
     // Take field
-    pub fn take_author(&mut self) -> ::std::string::String {
-        self.author.take().unwrap_or_else(|| ::std::string::String::new())
+    pub fn take_author(&mut self) -> ::std::vec::Vec<u8> {
+        self.author.take().unwrap_or_else(|| ::std::vec::Vec::new())
     }
 
-    pub fn get_author<'a>(&'a self) -> &'a str {
+    pub fn get_author<'a>(&'a self) -> &'a [u8] {
         match self.author.as_ref() {
             Some(v) => &v,
-            None => "",
+            None => &[],
         }
     }
 
@@ -984,7 +984,7 @@ impl ::protobuf::Message for Record {
                         return ::std::result::Result::Err(::protobuf::ProtobufError::WireError("unexpected wire type".to_string()));
                     };
                     let tmp = self.author.set_default();
-                    try!(is.read_string_into(tmp))
+                    try!(is.read_bytes_into(tmp))
                 },
                 4 => {
                     if wire_type != ::protobuf::wire_format::WireTypeLengthDelimited {
@@ -1013,7 +1013,7 @@ impl ::protobuf::Message for Record {
             my_size += ::protobuf::rt::bytes_size(2, &value);
         };
         for value in self.author.iter() {
-            my_size += ::protobuf::rt::string_size(3, &value);
+            my_size += ::protobuf::rt::bytes_size(3, &value);
         };
         for value in self.signature.iter() {
             my_size += ::protobuf::rt::bytes_size(4, &value);
@@ -1031,7 +1031,7 @@ impl ::protobuf::Message for Record {
             try!(os.write_bytes(2, &v));
         };
         if let Some(v) = self.author.as_ref() {
-            try!(os.write_string(3, &v));
+            try!(os.write_bytes(3, &v));
         };
         if let Some(v) = self.signature.as_ref() {
             try!(os.write_bytes(4, &v));
@@ -1088,7 +1088,7 @@ impl ::protobuf::MessageStatic for Record {
                     Record::has_value,
                     Record::get_value,
                 ));
-                fields.push(::protobuf::reflect::accessor::make_singular_string_accessor(
+                fields.push(::protobuf::reflect::accessor::make_singular_bytes_accessor(
                     "author",
                     Record::has_author,
                     Record::get_author,
@@ -1170,7 +1170,7 @@ static file_descriptor_proto_data: &'static [u8] = &[
     0x54, 0x10, 0x03, 0x22, 0x47, 0x0a, 0x06, 0x52, 0x65, 0x63, 0x6f, 0x72, 0x64, 0x12, 0x0b, 0x0a,
     0x03, 0x6b, 0x65, 0x79, 0x18, 0x01, 0x20, 0x01, 0x28, 0x09, 0x12, 0x0d, 0x0a, 0x05, 0x76, 0x61,
     0x6c, 0x75, 0x65, 0x18, 0x02, 0x20, 0x01, 0x28, 0x0c, 0x12, 0x0e, 0x0a, 0x06, 0x61, 0x75, 0x74,
-    0x68, 0x6f, 0x72, 0x18, 0x03, 0x20, 0x01, 0x28, 0x09, 0x12, 0x11, 0x0a, 0x09, 0x73, 0x69, 0x67,
+    0x68, 0x6f, 0x72, 0x18, 0x03, 0x20, 0x01, 0x28, 0x0c, 0x12, 0x11, 0x0a, 0x09, 0x73, 0x69, 0x67,
     0x6e, 0x61, 0x74, 0x75, 0x72, 0x65, 0x18, 0x04, 0x20, 0x01, 0x28, 0x0c,
 ];
 
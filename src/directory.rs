@@ -0,0 +1,219 @@
+//! Building and listing unixfs directories
+//!
+//! A directory is a PBNode whose unixfs data has type `Directory` and whose links are its
+//! entries, sorted by name, with each link's `Tsize` holding the cumulative size of the entry.
+
+use std::fs;
+use std::path::Path;
+
+use protobuf::core::Message;
+use protobuf::repeated::RepeatedField;
+
+use blockstore::Blockstore;
+use error::{Error, Result};
+use importer::{import_file, ImportOptions};
+use merkledag::{PBNode, PBLink};
+use multihash::{Multihash, MultihashBytes};
+use reader::load_unixfs;
+use unixfs;
+use ::{Node, write_node};
+
+/// Collects named links and turns them into a directory node
+#[derive(Debug, Default)]
+pub struct DirectoryBuilder {
+    links: Vec<PBLink>,
+}
+
+impl DirectoryBuilder {
+    pub fn new() -> DirectoryBuilder {
+        DirectoryBuilder{ links: Vec::new() }
+    }
+
+    /// Adds an entry pointing at `hash`, where `size` is the cumulative size of the entry
+    pub fn add_link<M: Multihash>(&mut self, name: &str, hash: M, size: u64) -> Result<()> {
+        if name.is_empty() || name.contains('/') {
+            return Err(Error::Unixfs(format!("{:?} is not a valid directory entry name", name)));
+        }
+        if self.links.iter().any(|l| l.get_Name() == name) {
+            return Err(Error::Unixfs(format!("duplicate directory entry {:?}", name)));
+        }
+
+        let mut link = PBLink::new();
+        link.set_Hash(try!(hash.as_bytes()));
+        link.set_Name(name.to_owned());
+        link.set_Tsize(size);
+        self.links.push(link);
+        Ok(())
+    }
+
+    /// Adds an entry pointing at a node we already have in memory
+    pub fn add_node(&mut self, name: &str, node: &Node) -> Result<()> {
+        let size = try!(node.cumulative_size());
+        self.add_link(name, &node.mh_bytes, size)
+    }
+
+    /// Builds the directory node and writes it to the store
+    pub fn build<B: Blockstore>(mut self, store: &mut B) -> Result<Node> {
+        self.links.sort_by(|a, b| a.get_Name().cmp(b.get_Name()));
+
+        let mut data = unixfs::Data::new();
+        data.set_Type(unixfs::Data_DataType::Directory);
+
+        let mut pbnode = PBNode::new();
+        pbnode.set_Links(RepeatedField::from_vec(self.links));
+        pbnode.set_Data(try!(data.write_to_bytes()));
+
+        let node = try!(Node::from_pb(pbnode));
+        try!(write_node(store, &node));
+        Ok(node)
+    }
+}
+
+/// Recursively imports a file or directory from the local filesystem, returning the root node
+///
+/// Inside a directory, anything that is neither a regular file nor a directory is skipped,
+/// including symbolic links, so a link back up the tree can't send the import round in circles.
+pub fn import_path<B: Blockstore, P: AsRef<Path>>(store: &mut B, path: P, opts: &ImportOptions) -> Result<Node> {
+    let path = path.as_ref();
+    let meta = try!(fs::metadata(path));
+    if meta.is_file() {
+        return import_file(store, try!(fs::File::open(path)), opts);
+    }
+
+    let mut builder = DirectoryBuilder::new();
+    for entry in try!(fs::read_dir(path)) {
+        let entry = try!(entry);
+        let child_path = entry.path();
+        // doesn't follow symlinks, so they are neither files nor directories here
+        let child_meta = try!(fs::symlink_metadata(&child_path));
+        if !child_meta.is_file() && !child_meta.is_dir() {
+            continue;
+        }
+
+        let name = try!(entry.file_name().into_string().map_err(|n| {
+            Error::Unixfs(format!("{:?} is not valid unicode", n))
+        }));
+        let child = try!(import_path(store, &child_path, opts));
+        try!(builder.add_node(&name, &child));
+    }
+    builder.build(store)
+}
+
+/// One entry in a directory listing
+#[derive(Clone, Debug)]
+pub struct DirectoryEntry {
+    pub name: String,
+    pub hash: MultihashBytes,
+    pub kind: unixfs::Data_DataType,
+    /// The file size for files, and the cumulative size for everything else
+    pub size: u64,
+}
+
+/// Lists the entries of a directory, loading each child to find out what it is
+pub fn list_directory<B: Blockstore, M: Multihash>(store: &B, hash: M) -> Result<Vec<DirectoryEntry>> {
+    let (pbnode, data) = try!(load_unixfs(store, &hash));
+    if data.get_Type() != unixfs::Data_DataType::Directory {
+        return Err(Error::Unixfs(format!("{} is a {:?}, not a directory", hash.base58(), data.get_Type())));
+    }
+
+    let mut entries = Vec::with_capacity(pbnode.get_Links().len());
+    for link in pbnode.get_Links() {
+        let child_hash = MultihashBytes(link.get_Hash().to_vec());
+        let (_, child_data) = try!(load_unixfs(store, &child_hash));
+        let kind = child_data.get_Type();
+        let size = match kind {
+            unixfs::Data_DataType::File | unixfs::Data_DataType::Raw => child_data.get_filesize(),
+            _ => link.get_Tsize()
+        };
+
+        entries.push(DirectoryEntry{ name: link.get_Name().to_owned(), hash: child_hash, kind: kind, size: size });
+    }
+    Ok(entries)
+}
+
+
+#[test]
+fn test_empty_directory() {
+    use blockstore::MemBlockstore;
+
+    let mut store = MemBlockstore::new();
+    let dir = DirectoryBuilder::new().build(&mut store).unwrap();
+    assert_eq!(dir.mh_str.0, "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn");
+    assert_eq!(list_directory(&store, &dir.mh_bytes).unwrap().len(), 0);
+}
+
+#[test]
+fn test_build_and_list_directory() {
+    use blockstore::MemBlockstore;
+
+    let mut store = MemBlockstore::new();
+    let file = import_file(&mut store, &b"hello world\n"[..], &ImportOptions::default()).unwrap();
+
+    let mut sub = DirectoryBuilder::new();
+    sub.add_node("a.txt", &file).unwrap();
+    let sub = sub.build(&mut store).unwrap();
+    assert_eq!(sub.mh_str.0, "QmVa4SiL7NizBsHzzudfME4xFN2PgbMCPiJqzECLBshKNx");
+
+    // added out of order, to check that the links get sorted
+    let mut root = DirectoryBuilder::new();
+    root.add_node("sub", &sub).unwrap();
+    root.add_node("hello.txt", &file).unwrap();
+    assert!(root.add_node("sub", &sub).is_err());
+    assert!(root.add_node("a/b", &sub).is_err());
+    let root = root.build(&mut store).unwrap();
+    assert_eq!(root.mh_str.0, "QmUXiXgZxpe2gAqtU4YXRs2HCk2dzemBJprygeACYzX1m8");
+    assert_eq!(root.cumulative_size().unwrap(), 191);
+
+    let entries = list_directory(&store, &root.mh_bytes).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].name, "hello.txt");
+    assert_eq!(entries[0].hash, file.mh_bytes);
+    assert_eq!(entries[0].kind, unixfs::Data_DataType::File);
+    assert_eq!(entries[0].size, 12);
+    assert_eq!(entries[1].name, "sub");
+    assert_eq!(entries[1].kind, unixfs::Data_DataType::Directory);
+    assert_eq!(entries[1].size, sub.cumulative_size().unwrap());
+
+    assert!(list_directory(&store, &file.mh_bytes).is_err());
+}
+
+#[test]
+fn test_import_path() {
+    use std::io::Write;
+    use blockstore::MemBlockstore;
+
+    let root = ::test_dir("import_path");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::File::create(root.join("hello.txt")).unwrap().write_all(b"hello world\n").unwrap();
+    fs::File::create(root.join("sub").join("a.txt")).unwrap().write_all(b"hello world\n").unwrap();
+
+    let mut store = MemBlockstore::new();
+    let dir = import_path(&mut store, &root, &ImportOptions::default()).unwrap();
+    assert_eq!(dir.mh_str.0, "QmUXiXgZxpe2gAqtU4YXRs2HCk2dzemBJprygeACYzX1m8");
+    // the file is deduplicated, so just the file and two directories
+    assert_eq!(store.len(), 3);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_import_path_skips_symlinks() {
+    use std::io::Write;
+    use std::os::unix::fs::symlink;
+    use blockstore::MemBlockstore;
+
+    let root = ::test_dir("import_symlinks");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::File::create(root.join("hello.txt")).unwrap().write_all(b"hello world\n").unwrap();
+    let mut store = MemBlockstore::new();
+    let before = import_path(&mut store, &root, &ImportOptions::default()).unwrap();
+
+    // a cycle back to the root, and a link to a file
+    symlink(&root, root.join("sub").join("loop")).unwrap();
+    symlink(root.join("hello.txt"), root.join("link.txt")).unwrap();
+    let after = import_path(&mut store, &root, &ImportOptions::default()).unwrap();
+    assert_eq!(after.mh_str, before.mh_str);
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,199 @@
+//! The error type shared by the whole crate
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+use protobuf::error::ProtobufError;
+use rust_base58::base58::FromBase58Error;
+use rustc_serialize::base64::FromBase64Error;
+use rustc_serialize::json::ParserError;
+
+/// Everything that can go wrong while reading or writing ipfs objects
+#[derive(Debug)]
+pub enum Error {
+    /// An underlying I/O operation failed
+    Io(io::Error),
+    /// A protobuf message could not be encoded or decoded
+    Protobuf(ProtobufError),
+    /// A string was not valid base58
+    Base58(FromBase58Error),
+    /// A string was not valid base64
+    Base64(FromBase64Error),
+    /// A string was not valid hex
+    Hex(String),
+    /// Some bytes were not a valid multihash
+    Multihash(String),
+    /// The requested block isn't in the blockstore (the base58 multihash of the block)
+    MissingBlock(String),
+    /// A block's contents don't match its hash (the expected and actual base58 multihashes)
+    HashMismatch(String, String),
+    /// A key could not be parsed
+    KeyParse(String),
+    /// A varint was truncated or too large
+    Varint(String),
+    /// A CID was malformed or unsupported
+    Cid(String),
+    /// A multibase string had an unknown prefix or bad characters
+    Multibase(String),
+    /// A node's unixfs data was malformed, or wasn't the expected type
+    Unixfs(String),
+    /// A string couldn't be parsed as an ipfs path
+    Path(String),
+    /// A path went through something that isn't a directory (the path up to that point)
+    NotADirectory(String),
+    /// A directory has no link with the given name (the directory's path, and the name)
+    NoSuchLink(String, String),
+    /// A stream ended part way through a length-prefixed frame
+    TruncatedFrame(String),
+    /// A frame was longer than allowed (the frame length, and the limit)
+    FrameTooLarge(u64, usize),
+    /// A DHT message was malformed or unexpected
+    Dht(String),
+    /// A peer didn't answer in time (the peer ID)
+    Timeout(String),
+    /// A DHT record wasn't signed, or its author or signature didn't check out
+    BadRecord(String),
+    /// A public key doesn't belong to the expected peer (the expected and actual peer IDs)
+    PeerIdMismatch(String, String),
+    /// A keystore operation failed, such as a missing or duplicate key name
+    Keystore(String),
+    /// A file was not valid JSON
+    Json(ParserError),
+    /// The repo config was missing a field or had one of the wrong type
+    Config(String),
+    /// A multiaddr was malformed or used an unknown protocol
+    Multiaddr(String),
+    /// multistream-select negotiation failed
+    Multistream(String),
+    /// An address can't be dialed or listened on
+    Transport(String),
+    /// Opening another connection would go over a limit
+    ConnectionLimit(String),
+    /// The secure channel handshake failed, or a frame didn't decrypt
+    SecureChannel(String),
+    /// The stream multiplexer got a malformed frame, or the session was closed
+    Mux(String),
+    /// Options passed in by the caller are out of range
+    InvalidOptions(String),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::Protobuf(ref e) => write!(f, "protobuf error: {:?}", e),
+            Error::Base58(ref e) => write!(f, "invalid base58: {:?}", e),
+            Error::Base64(ref e) => write!(f, "invalid base64: {}", e),
+            Error::Hex(ref s) => write!(f, "invalid hex: {}", s),
+            Error::Multihash(ref s) => write!(f, "invalid multihash: {}", s),
+            Error::MissingBlock(ref s) => write!(f, "block not found: {}", s),
+            Error::HashMismatch(ref expected, ref actual) => write!(f, "block {} has contents that hash to {}", expected, actual),
+            Error::KeyParse(ref s) => write!(f, "could not parse key: {}", s),
+            Error::Varint(ref s) => write!(f, "invalid varint: {}", s),
+            Error::Cid(ref s) => write!(f, "invalid CID: {}", s),
+            Error::Multibase(ref s) => write!(f, "invalid multibase: {}", s),
+            Error::Unixfs(ref s) => write!(f, "unixfs error: {}", s),
+            Error::Path(ref s) => write!(f, "invalid path: {}", s),
+            Error::NotADirectory(ref s) => write!(f, "{} is not a directory", s),
+            Error::NoSuchLink(ref dir, ref name) => write!(f, "{} has no link named {:?}", dir, name),
+            Error::TruncatedFrame(ref s) => write!(f, "truncated frame: {}", s),
+            Error::FrameTooLarge(len, max) => write!(f, "frame of {} bytes is larger than the limit of {}", len, max),
+            Error::Dht(ref s) => write!(f, "DHT error: {}", s),
+            Error::Timeout(ref s) => write!(f, "timed out waiting for {}", s),
+            Error::BadRecord(ref s) => write!(f, "invalid record: {}", s),
+            Error::PeerIdMismatch(ref expected, ref actual) => write!(f, "expected a key for peer {} but got one for {}", expected, actual),
+            Error::Keystore(ref s) => write!(f, "keystore error: {}", s),
+            Error::Json(ref e) => write!(f, "invalid JSON: {}", e),
+            Error::Config(ref s) => write!(f, "invalid config: {}", s),
+            Error::Multiaddr(ref s) => write!(f, "invalid multiaddr: {}", s),
+            Error::Multistream(ref s) => write!(f, "protocol negotiation failed: {}", s),
+            Error::Transport(ref s) => write!(f, "transport error: {}", s),
+            Error::ConnectionLimit(ref s) => write!(f, "connection limit reached: {}", s),
+            Error::SecureChannel(ref s) => write!(f, "secure channel error: {}", s),
+            Error::Mux(ref s) => write!(f, "stream multiplexer error: {}", s),
+            Error::InvalidOptions(ref s) => write!(f, "invalid options: {}", s),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(_) => "I/O error",
+            Error::Protobuf(_) => "protobuf error",
+            Error::Base58(_) => "invalid base58",
+            Error::Base64(_) => "invalid base64",
+            Error::Hex(_) => "invalid hex",
+            Error::Multihash(_) => "invalid multihash",
+            Error::MissingBlock(_) => "block not found",
+            Error::HashMismatch(..) => "block contents don't match hash",
+            Error::KeyParse(_) => "could not parse key",
+            Error::Varint(_) => "invalid varint",
+            Error::Cid(_) => "invalid CID",
+            Error::Multibase(_) => "invalid multibase",
+            Error::Unixfs(_) => "unixfs error",
+            Error::Path(_) => "invalid path",
+            Error::NotADirectory(_) => "not a directory",
+            Error::NoSuchLink(..) => "no such link",
+            Error::TruncatedFrame(_) => "truncated frame",
+            Error::FrameTooLarge(..) => "frame too large",
+            Error::Dht(_) => "DHT error",
+            Error::Timeout(_) => "timed out",
+            Error::BadRecord(_) => "invalid record",
+            Error::PeerIdMismatch(..) => "public key doesn't match peer ID",
+            Error::Keystore(_) => "keystore error",
+            Error::Json(_) => "invalid JSON",
+            Error::Config(_) => "invalid config",
+            Error::Multiaddr(_) => "invalid multiaddr",
+            Error::Multistream(_) => "protocol negotiation failed",
+            Error::Transport(_) => "transport error",
+            Error::ConnectionLimit(_) => "connection limit reached",
+            Error::SecureChannel(_) => "secure channel error",
+            Error::Mux(_) => "stream multiplexer error",
+            Error::InvalidOptions(_) => "invalid options",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Base64(ref e) => Some(e),
+            Error::Json(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<ProtobufError> for Error {
+    fn from(e: ProtobufError) -> Error {
+        Error::Protobuf(e)
+    }
+}
+
+impl From<FromBase58Error> for Error {
+    fn from(e: FromBase58Error) -> Error {
+        Error::Base58(e)
+    }
+}
+
+impl From<FromBase64Error> for Error {
+    fn from(e: FromBase64Error) -> Error {
+        Error::Base64(e)
+    }
+}
+
+impl From<ParserError> for Error {
+    fn from(e: ParserError) -> Error {
+        Error::Json(e)
+    }
+}
@@ -0,0 +1,241 @@
+//! Answers incoming DHT requests
+//!
+//! `handle_message` is a plain function from (state, request) to response, with no I/O, so the
+//! same code can be driven by a real connection or called directly in tests.
+
+use std::collections::HashMap;
+
+use protobuf::RepeatedField;
+
+use dht::{Message, Message_MessageType, Message_Peer, Record};
+use error::{Error, Result};
+use routing::{Key, RoutingTable};
+
+/// Records stored with `PUT_VALUE`, by key
+#[derive(Clone, Debug, Default)]
+pub struct RecordStore {
+    records: HashMap<String, Record>,
+}
+
+impl RecordStore {
+    pub fn new() -> RecordStore {
+        RecordStore{ records: HashMap::new() }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Record> {
+        self.records.get(key)
+    }
+
+    pub fn put(&mut self, record: Record) {
+        self.records.insert(record.get_key().to_owned(), record);
+    }
+}
+
+/// Peers that have announced they can provide a key, with `ADD_PROVIDER`
+#[derive(Clone, Debug, Default)]
+pub struct ProviderStore {
+    providers: HashMap<String, Vec<Message_Peer>>,
+}
+
+impl ProviderStore {
+    pub fn new() -> ProviderStore {
+        ProviderStore{ providers: HashMap::new() }
+    }
+
+    pub fn get(&self, key: &str) -> &[Message_Peer] {
+        self.providers.get(key).map(|v| &v[..]).unwrap_or(&[])
+    }
+
+    /// Adds a provider for a key, replacing any earlier entry for the same peer
+    pub fn add(&mut self, key: &str, provider: Message_Peer) {
+        let list = self.providers.entry(key.to_owned()).or_insert_with(Vec::new);
+        list.retain(|p| p.get_id() != provider.get_id());
+        list.push(provider);
+    }
+}
+
+/// Everything a DHT node needs to answer requests
+#[derive(Clone, Debug)]
+pub struct DhtState {
+    pub routing: RoutingTable,
+    pub records: RecordStore,
+    pub providers: ProviderStore,
+    /// How many peers to return in `closerPeers`
+    pub k: usize,
+}
+
+impl DhtState {
+    pub fn new(routing: RoutingTable, k: usize) -> DhtState {
+        DhtState{ routing: routing, records: RecordStore::new(), providers: ProviderStore::new(), k: k }
+    }
+}
+
+/// The point in the keyspace that a request is about
+///
+/// `FIND_NODE` keys are base58 peer IDs; every other key is hashed as-is.
+pub fn target_key(msg_type: Message_MessageType, key: &str) -> Result<Key> {
+    match msg_type {
+        Message_MessageType::FIND_NODE => Key::from_peer_id(key),
+        _ => Key::from_bytes(key.as_bytes())
+    }
+}
+
+fn closer_peers(state: &DhtState, from: &Message_Peer, msg_type: Message_MessageType, key: &str) -> Result<RepeatedField<Message_Peer>> {
+    let target = try!(target_key(msg_type, key));
+    // ask for one extra, in case the requester is among them
+    let peers = state.routing.closest_peers(&target, state.k + 1).into_iter()
+        .filter(|p| p.get_id() != from.get_id())
+        .take(state.k)
+        .collect();
+    Ok(RepeatedField::from_vec(peers))
+}
+
+/// Handles one request from the peer `from`
+///
+/// Returns the response to send back, or `None` for requests that don't get one
+/// (`ADD_PROVIDER`).
+pub fn handle_message(state: &mut DhtState, from: &Message_Peer, msg: &Message) -> Result<Option<Message>> {
+    if !msg.has_field_type() {
+        return Err(Error::Dht("message has no type".to_owned()));
+    }
+    let msg_type = msg.get_field_type();
+    let key = msg.get_key();
+
+    let mut resp = Message::new();
+    resp.set_field_type(msg_type);
+    resp.set_key(key.to_owned());
+
+    match msg_type {
+        Message_MessageType::PING => {
+            return Ok(Some(msg.clone()));
+        }
+        Message_MessageType::FIND_NODE => {
+            resp.set_closerPeers(try!(closer_peers(state, from, msg_type, key)));
+        }
+        Message_MessageType::GET_VALUE => {
+            if let Some(record) = state.records.get(key) {
+                resp.set_record(record.clone());
+            }
+            resp.set_closerPeers(try!(closer_peers(state, from, msg_type, key)));
+        }
+        Message_MessageType::PUT_VALUE => {
+            if !msg.has_record() {
+                return Err(Error::Dht("PUT_VALUE without a record".to_owned()));
+            }
+            if msg.get_record().get_key() != key {
+                return Err(Error::Dht(format!("record key {:?} doesn't match message key {:?}", msg.get_record().get_key(), key)));
+            }
+            state.records.put(msg.get_record().clone());
+            return Ok(Some(msg.clone()));
+        }
+        Message_MessageType::ADD_PROVIDER => {
+            // peers may only announce themselves
+            for provider in msg.get_providerPeers() {
+                if provider.get_id() == from.get_id() {
+                    state.providers.add(key, provider.clone());
+                }
+            }
+            return Ok(None);
+        }
+        Message_MessageType::GET_PROVIDERS => {
+            resp.set_providerPeers(RepeatedField::from_vec(state.providers.get(key).to_vec()));
+            resp.set_closerPeers(try!(closer_peers(state, from, msg_type, key)));
+        }
+    }
+
+    Ok(Some(resp))
+}
+
+
+#[cfg(test)]
+fn test_state() -> (DhtState, Vec<Message_Peer>) {
+    use rust_base58::ToBase58;
+    use rust_multihash::HashTypes;
+    use routing::DEFAULT_BUCKET_SIZE;
+
+    let peers: Vec<Message_Peer> = (0..30).map(|i| {
+        let mut peer = Message_Peer::new();
+        peer.set_id(::hash_bytes(HashTypes::SHA2256, format!("peer{}", i).into_bytes()).unwrap().to_base58());
+        peer
+    }).collect();
+
+    let local = Key::from_peer_id(peers[0].get_id()).unwrap();
+    let mut state = DhtState::new(RoutingTable::new(local, DEFAULT_BUCKET_SIZE), 5);
+    for p in &peers[1..] {
+        state.routing.update(p.clone()).unwrap();
+    }
+    (state, peers)
+}
+
+#[test]
+fn test_ping() {
+    let (mut state, peers) = test_state();
+    let mut ping = Message::new();
+    ping.set_field_type(Message_MessageType::PING);
+    assert_eq!(handle_message(&mut state, &peers[1], &ping).unwrap(), Some(ping));
+
+    assert!(handle_message(&mut state, &peers[1], &Message::new()).is_err());
+}
+
+#[test]
+fn test_find_node() {
+    let (mut state, peers) = test_state();
+    let mut req = Message::new();
+    req.set_field_type(Message_MessageType::FIND_NODE);
+    req.set_key(peers[7].get_id().to_owned());
+
+    let resp = handle_message(&mut state, &peers[1], &req).unwrap().unwrap();
+    assert_eq!(resp.get_field_type(), Message_MessageType::FIND_NODE);
+    assert_eq!(resp.get_closerPeers().len(), 5);
+    assert!(resp.get_closerPeers().iter().all(|p| p.get_id() != peers[1].get_id()));
+    if state.routing.contains(peers[7].get_id()) {
+        // the target itself is as close as it gets
+        assert_eq!(resp.get_closerPeers()[0].get_id(), peers[7].get_id());
+    }
+}
+
+#[test]
+fn test_put_and_get_value() {
+    let (mut state, peers) = test_state();
+
+    let mut get = Message::new();
+    get.set_field_type(Message_MessageType::GET_VALUE);
+    get.set_key("/v/hello".to_owned());
+    let resp = handle_message(&mut state, &peers[1], &get).unwrap().unwrap();
+    assert!(!resp.has_record());
+    assert_eq!(resp.get_closerPeers().len(), 5);
+
+    let mut record = Record::new();
+    record.set_key("/v/hello".to_owned());
+    record.set_value(b"world".to_vec());
+    let mut put = Message::new();
+    put.set_field_type(Message_MessageType::PUT_VALUE);
+    put.set_key("/v/hello".to_owned());
+    put.set_record(record.clone());
+    assert_eq!(handle_message(&mut state, &peers[1], &put).unwrap(), Some(put.clone()));
+
+    let resp = handle_message(&mut state, &peers[2], &get).unwrap().unwrap();
+    assert_eq!(resp.get_record(), &record);
+
+    put.set_key("/v/other".to_owned());
+    assert!(handle_message(&mut state, &peers[1], &put).is_err());
+}
+
+#[test]
+fn test_providers() {
+    let (mut state, peers) = test_state();
+
+    let mut add = Message::new();
+    add.set_field_type(Message_MessageType::ADD_PROVIDER);
+    add.set_key("QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe".to_owned());
+    add.set_providerPeers(RepeatedField::from_vec(vec![peers[3].clone(), peers[4].clone()]));
+    // peer 3 can announce itself, but not peer 4
+    assert_eq!(handle_message(&mut state, &peers[3], &add).unwrap(), None);
+
+    let mut get = Message::new();
+    get.set_field_type(Message_MessageType::GET_PROVIDERS);
+    get.set_key("QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe".to_owned());
+    let resp = handle_message(&mut state, &peers[1], &get).unwrap().unwrap();
+    assert_eq!(resp.get_providerPeers(), &[peers[3].clone()]);
+    assert_eq!(resp.get_closerPeers().len(), 5);
+}
@@ -0,0 +1,236 @@
+//! Turns a stream of bytes into a unixfs file DAG
+//!
+//! The input is split into fixed-size chunks, each of which becomes a leaf node.  The leaves are
+//! then arranged into a balanced tree the same way `ipfs add` does by default: the tree grows one
+//! level at a time, with the old root becoming the first child of the new root, and every
+//! intermediate node filled up to `max_links` children before moving on to the next one.
+
+use std::io::Read;
+
+use protobuf::core::Message;
+use protobuf::repeated::RepeatedField;
+
+use blockstore::Blockstore;
+use error::{Error, Result};
+use merkledag::{PBNode, PBLink};
+use unixfs;
+use ::{Node, write_node};
+
+/// The default chunk size used by `ipfs add`
+pub const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+
+/// The default maximum number of links in one intermediate node used by `ipfs add`
+pub const DEFAULT_MAX_LINKS: usize = 174;
+
+/// Parameters for building a file DAG
+#[derive(Clone, Debug)]
+pub struct ImportOptions {
+    /// The size of each leaf, except possibly the last one
+    pub chunk_size: usize,
+    /// The maximum number of children of an intermediate node
+    pub max_links: usize,
+}
+
+impl Default for ImportOptions {
+    fn default() -> ImportOptions {
+        ImportOptions{ chunk_size: DEFAULT_CHUNK_SIZE, max_links: DEFAULT_MAX_LINKS }
+    }
+}
+
+/// A node that has been written to the store, along with the sizes needed to link to it
+struct Child {
+    node: Node,
+    filesize: u64,
+    cumulative_size: u64,
+}
+
+/// Reads fixed-size chunks, looking one chunk ahead so we know when we've reached the end
+struct Chunker<R> {
+    reader: R,
+    chunk_size: usize,
+    next: Option<Vec<u8>>,
+}
+
+impl<R: Read> Chunker<R> {
+    fn new(reader: R, chunk_size: usize) -> Result<Chunker<R>> {
+        let mut chunker = Chunker{ reader: reader, chunk_size: chunk_size, next: None };
+        chunker.next = try!(chunker.read_chunk());
+        Ok(chunker)
+    }
+
+    fn read_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut buf = vec![0; self.chunk_size];
+        let mut filled = 0;
+        while filled < buf.len() {
+            let n = try!(self.reader.read(&mut buf[filled..]));
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        buf.truncate(filled);
+
+        Ok(if filled == 0 { None } else { Some(buf) })
+    }
+
+    fn done(&self) -> bool {
+        self.next.is_none()
+    }
+
+    fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        let upcoming = try!(self.read_chunk());
+        Ok(::std::mem::replace(&mut self.next, upcoming))
+    }
+}
+
+struct Builder<'a, B: Blockstore + 'a, R> {
+    store: &'a mut B,
+    chunker: Chunker<R>,
+    max_links: usize,
+}
+
+impl<'a, B: Blockstore, R: Read> Builder<'a, B, R> {
+    fn write(&mut self, pbnode: PBNode) -> Result<Node> {
+        let node = try!(Node::from_pb(pbnode));
+        try!(write_node(&mut *self.store, &node));
+        Ok(node)
+    }
+
+    fn leaf(&mut self) -> Result<Child> {
+        let chunk = try!(self.chunker.next_chunk()).unwrap_or_else(Vec::new);
+        let filesize = chunk.len() as u64;
+
+        let mut data = unixfs::Data::new();
+        data.set_Type(unixfs::Data_DataType::File);
+        data.set_filesize(filesize);
+        if !chunk.is_empty() {
+            data.set_Data(chunk);
+        }
+
+        let mut pbnode = PBNode::new();
+        pbnode.set_Data(try!(data.write_to_bytes()));
+
+        let node = try!(self.write(pbnode));
+        let cumulative_size = try!(node.cumulative_size());
+        Ok(Child{ node: node, filesize: filesize, cumulative_size: cumulative_size })
+    }
+
+    fn intermediate(&mut self, children: Vec<Child>) -> Result<Child> {
+        let filesize = children.iter().fold(0, |acc, c| acc + c.filesize);
+
+        let mut data = unixfs::Data::new();
+        data.set_Type(unixfs::Data_DataType::File);
+        data.set_filesize(filesize);
+        data.set_blocksizes(children.iter().map(|c| c.filesize).collect());
+
+        let links = children.iter().map(|c| {
+            let mut link = PBLink::new();
+            link.set_Hash(c.node.mh_bytes.0.clone());
+            // go-ipfs always writes the name, even when it's empty, and we want the same hashes
+            link.set_Name(String::new());
+            link.set_Tsize(c.cumulative_size);
+            link
+        }).collect();
+
+        let mut pbnode = PBNode::new();
+        pbnode.set_Links(RepeatedField::from_vec(links));
+        pbnode.set_Data(try!(data.write_to_bytes()));
+
+        let node = try!(self.write(pbnode));
+        let cumulative_size = try!(node.cumulative_size());
+        Ok(Child{ node: node, filesize: filesize, cumulative_size: cumulative_size })
+    }
+
+    /// Adds children to `children` until there are `max_links` of them or we run out of data,
+    /// where each child is a full tree of the given depth
+    fn fill(&mut self, mut children: Vec<Child>, depth: usize) -> Result<Child> {
+        while children.len() < self.max_links && !self.chunker.done() {
+            let child = if depth == 1 {
+                try!(self.leaf())
+            } else {
+                try!(self.fill(Vec::new(), depth - 1))
+            };
+            children.push(child);
+        }
+        self.intermediate(children)
+    }
+}
+
+/// Imports a file into the blockstore, returning the root node
+///
+/// Every node (leaves and intermediate nodes) is written to `store`.  An empty input produces a
+/// single empty file node.  A `chunk_size` of 0 or a `max_links` below 2 is an
+/// `Error::InvalidOptions`.
+pub fn import_file<B: Blockstore, R: Read>(store: &mut B, reader: R, opts: &ImportOptions) -> Result<Node> {
+    if opts.chunk_size == 0 {
+        return Err(Error::InvalidOptions("chunk_size must be positive".to_owned()));
+    }
+    if opts.max_links < 2 {
+        return Err(Error::InvalidOptions(format!("max_links must be at least 2, not {}", opts.max_links)));
+    }
+
+    let mut builder = Builder{ store: store, chunker: try!(Chunker::new(reader, opts.chunk_size)), max_links: opts.max_links };
+
+    let mut root = try!(builder.leaf());
+    let mut depth = 1;
+    while !builder.chunker.done() {
+        root = try!(builder.fill(vec![root], depth));
+        depth += 1;
+    }
+
+    Ok(root.node)
+}
+
+
+#[test]
+fn test_import_single_chunk() {
+    use blockstore::MemBlockstore;
+
+    let mut store = MemBlockstore::new();
+    let root = import_file(&mut store, &b"hello world\n"[..], &ImportOptions::default()).unwrap();
+    assert_eq!(root.mh_str.0, "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o");
+    assert_eq!(store.len(), 1);
+
+    let root = import_file(&mut store, &b""[..], &ImportOptions::default()).unwrap();
+    assert_eq!(root.mh_str.0, "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH");
+}
+
+#[test]
+fn test_import_balanced() {
+    use blockstore::MemBlockstore;
+
+    let mut store = MemBlockstore::new();
+    let opts = ImportOptions{ chunk_size: 4, max_links: 3 };
+    let root = import_file(&mut store, &b"hello world, this is ipfs"[..], &opts).unwrap();
+    assert_eq!(root.mh_str.0, "QmTFXgAturC329s6rtooV5k7oGi9oGzjb3tqxW7pLgGTj9");
+    assert_eq!(root.cumulative_size().unwrap(), 547);
+    // 7 leaves, 3 nodes one level up, and the root
+    assert_eq!(store.len(), 11);
+
+    let pbnode = root.get_node();
+    let mut data = unixfs::Data::new();
+    data.merge_from_bytes(pbnode.get_Data()).unwrap();
+    assert_eq!(data.get_filesize(), 25);
+    assert_eq!(data.get_blocksizes(), &[12, 12, 1]);
+    assert_eq!(pbnode.get_Links().len(), 3);
+
+    let mut store = MemBlockstore::new();
+    let opts = ImportOptions{ chunk_size: 4, max_links: 174 };
+    let root = import_file(&mut store, &b"abcdefgh"[..], &opts).unwrap();
+    assert_eq!(root.mh_str.0, "QmUJSyff6SExmRg9qwfp8Wfzd4Eyf4gvUgU2uQaELbbBaz");
+    assert_eq!(root.cumulative_size().unwrap(), 118);
+}
+
+#[test]
+fn test_invalid_options() {
+    use blockstore::MemBlockstore;
+
+    let mut store = MemBlockstore::new();
+    for opts in &[ImportOptions{ chunk_size: 0, max_links: 10 }, ImportOptions{ chunk_size: 10, max_links: 1 }] {
+        match import_file(&mut store, &b"hello world\n"[..], opts) {
+            Err(Error::InvalidOptions(_)) => {}
+            other => panic!("unexpected result for {:?}: {:?}", opts, other)
+        }
+    }
+    assert_eq!(store.len(), 0);
+}
@@ -0,0 +1,196 @@
+//! Signing and verifying with the keys in `crypto::PrivateKey` and `crypto::PublicKey`
+//!
+//! Everything here dispatches on the key's `KeyType`:
+//!
+//! * RSA private keys hold a DER `RSAPrivateKey`, public keys a DER `SubjectPublicKeyInfo`, and
+//!   signatures are PKCS#1 v1.5 over the SHA-256 of the data, the same as go-ipfs.
+//! * Ed25519 private keys hold the 32 byte seed followed by the 32 byte public key, and public
+//!   keys hold just the public key.  Some older implementations append the public key a second
+//!   time, so 96 byte private keys are accepted too.
+
+use openssl::crypto::hash;
+use openssl::crypto::pkey::PKey;
+use protobuf::core::Message;
+use rand::{OsRng, Rng};
+use rust_crypto::ed25519;
+use rust_multihash::HashTypes;
+
+use crypto::{KeyType, PrivateKey, PublicKey};
+use error::{Error, Result};
+use multihash::ParsedMultihash;
+use ::hash_bytes;
+
+/// Serialized public keys up to this long are put in peer IDs directly, rather than hashed
+pub const MAX_INLINE_KEY_LENGTH: usize = 42;
+
+/// The multihash code of the identity "hash", which is just the data itself
+pub const IDENTITY_CODE: u8 = 0x00;
+
+/// Loads an RSA private key into openssl
+pub fn private_pkey(key: &PrivateKey) -> Result<PKey> {
+    if key.get_Type() != KeyType::RSA {
+        return Err(Error::KeyParse(format!("openssl keys must be RSA, not {:?}", key.get_Type())));
+    }
+    let mut pkey = PKey::new();
+    pkey.load_priv(key.get_Data());
+    Ok(pkey)
+}
+
+/// Loads an RSA public key into openssl
+pub fn public_pkey(key: &PublicKey) -> Result<PKey> {
+    if key.get_Type() != KeyType::RSA {
+        return Err(Error::KeyParse(format!("openssl keys must be RSA, not {:?}", key.get_Type())));
+    }
+    let mut pkey = PKey::new();
+    pkey.load_pub(key.get_Data());
+    Ok(pkey)
+}
+
+/// The 64 byte seed-and-public-key form of an Ed25519 private key
+fn ed25519_secret(key: &PrivateKey) -> Result<&[u8]> {
+    let data = key.get_Data();
+    if data.len() != 64 && data.len() != 96 {
+        return Err(Error::KeyParse(format!("Ed25519 private keys are 64 bytes, not {}", data.len())));
+    }
+    Ok(&data[..64])
+}
+
+/// The public half of a private key
+pub fn public_key(key: &PrivateKey) -> Result<PublicKey> {
+    let data = match key.get_Type() {
+        KeyType::RSA => try!(private_pkey(key)).save_pub(),
+        KeyType::Ed25519 => try!(ed25519_secret(key))[32..].to_vec(),
+    };
+    let mut pubkey = PublicKey::new();
+    pubkey.set_Type(key.get_Type());
+    pubkey.set_Data(data);
+    Ok(pubkey)
+}
+
+/// The multihash that identifies a public key, and so the bytes of its peer ID
+///
+/// Keys that serialize to at most `MAX_INLINE_KEY_LENGTH` bytes, such as Ed25519 keys, are
+/// embedded as-is with the identity multihash; larger ones are hashed with SHA-256.
+pub fn public_key_hash(key: &PublicKey) -> Result<Vec<u8>> {
+    let bytes = try!(key.write_to_bytes());
+    if bytes.len() <= MAX_INLINE_KEY_LENGTH {
+        return Ok(try!(ParsedMultihash::new(IDENTITY_CODE, bytes)).to_bytes());
+    }
+    hash_bytes(HashTypes::SHA2256, bytes)
+}
+
+/// Signs `data` with a private key
+pub fn sign(key: &PrivateKey, data: &[u8]) -> Result<Vec<u8>> {
+    match key.get_Type() {
+        KeyType::RSA => {
+            let pkey = try!(private_pkey(key));
+            let digest = hash::hash(hash::Type::SHA256, data);
+            Ok(pkey.sign_with_hash(&digest, hash::Type::SHA256))
+        }
+        KeyType::Ed25519 => {
+            Ok(ed25519::signature(data, try!(ed25519_secret(key))).to_vec())
+        }
+    }
+}
+
+/// Checks a signature made by `sign`
+pub fn verify(key: &PublicKey, data: &[u8], signature: &[u8]) -> Result<bool> {
+    match key.get_Type() {
+        KeyType::RSA => {
+            let pkey = try!(public_pkey(key));
+            let digest = hash::hash(hash::Type::SHA256, data);
+            Ok(pkey.verify_with_hash(&digest, signature, hash::Type::SHA256))
+        }
+        KeyType::Ed25519 => {
+            if key.get_Data().len() != 32 {
+                return Err(Error::KeyParse(format!("Ed25519 public keys are 32 bytes, not {}", key.get_Data().len())));
+            }
+            Ok(signature.len() == 64 && ed25519::verify(data, key.get_Data(), signature))
+        }
+    }
+}
+
+/// Generates a new RSA key with the given number of bits
+pub fn generate_rsa(bits: usize) -> PrivateKey {
+    let mut pkey = PKey::new();
+    pkey.gen(bits);
+    let mut key = PrivateKey::new();
+    key.set_Type(KeyType::RSA);
+    key.set_Data(pkey.save_priv());
+    key
+}
+
+/// The Ed25519 key derived from a 32 byte seed
+pub fn ed25519_from_seed(seed: &[u8; 32]) -> PrivateKey {
+    let (secret, _) = ed25519::keypair(seed);
+    let mut key = PrivateKey::new();
+    key.set_Type(KeyType::Ed25519);
+    key.set_Data(secret.to_vec());
+    key
+}
+
+/// Generates a new Ed25519 key from the operating system's random number generator
+pub fn generate_ed25519() -> Result<PrivateKey> {
+    let mut rng = try!(OsRng::new());
+    let mut seed = [0; 32];
+    rng.fill_bytes(&mut seed);
+    Ok(ed25519_from_seed(&seed))
+}
+
+/// Generates a new key of any type; `bits` only matters for RSA
+pub fn generate(key_type: KeyType, bits: usize) -> Result<PrivateKey> {
+    match key_type {
+        KeyType::RSA => Ok(generate_rsa(bits)),
+        KeyType::Ed25519 => generate_ed25519(),
+    }
+}
+
+
+#[test]
+fn test_sign_and_verify() {
+    for &key_type in &[KeyType::RSA, KeyType::Ed25519] {
+        let key = generate(key_type, 1024).unwrap();
+        let pubkey = public_key(&key).unwrap();
+        assert_eq!(pubkey.get_Type(), key_type);
+
+        let sig = sign(&key, b"hello world").unwrap();
+        assert!(verify(&pubkey, b"hello world", &sig).unwrap());
+        assert!(!verify(&pubkey, b"hello world!", &sig).unwrap());
+
+        let other = public_key(&generate(key_type, 1024).unwrap()).unwrap();
+        assert!(!verify(&other, b"hello world", &sig).unwrap());
+        assert!(public_key_hash(&pubkey).unwrap() != public_key_hash(&other).unwrap());
+    }
+}
+
+#[test]
+fn test_ed25519_vector() {
+    use ::{bin_to_hex, hex_to_bin};
+
+    // test 1 from RFC 8032
+    let mut seed = [0; 32];
+    seed.copy_from_slice(&hex_to_bin("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60").unwrap());
+    let key = ed25519_from_seed(&seed);
+    let pubkey = public_key(&key).unwrap();
+    assert_eq!(bin_to_hex(&pubkey.get_Data().to_vec()), "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+
+    let sig = sign(&key, b"").unwrap();
+    assert_eq!(bin_to_hex(&sig), "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b");
+    assert!(verify(&pubkey, b"", &sig).unwrap());
+    assert!(!verify(&pubkey, b"", &sig[..63]).unwrap());
+
+    // the key is small enough to be inlined in the peer ID
+    let mh = public_key_hash(&pubkey).unwrap();
+    assert_eq!(mh[0], IDENTITY_CODE);
+    assert_eq!(mh[2..].to_vec(), pubkey.write_to_bytes().unwrap());
+}
+
+#[test]
+fn test_bad_ed25519_keys() {
+    let mut key = PrivateKey::new();
+    key.set_Type(KeyType::Ed25519);
+    key.set_Data(vec![0; 10]);
+    assert!(public_key(&key).is_err());
+    assert!(sign(&key, b"data").is_err());
+    assert!(private_pkey(&key).is_err());
+}
@@ -0,0 +1,268 @@
+//! Named private keys, kept in the `keystore` directory of the repo
+//!
+//! Each key is stored in a file named after the key, holding a serialized `crypto::PrivateKey`,
+//! like go-ipfs does.  On unix, the directory is only accessible by its owner and key files are
+//! only readable by their owner.
+
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use protobuf::core::Message;
+use rustc_serialize::base64::{ToBase64, FromBase64, Config, CharacterSet, Newline};
+
+use blockstore::get_ipfs_path;
+use crypto::{KeyType, PrivateKey};
+use error::{Error, Result};
+use keys;
+use peer::PeerId;
+
+/// A directory of named private keys
+pub struct Keystore {
+    root: PathBuf,
+}
+
+/// Checks that a key name can safely be used as a file name
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(Error::Keystore("key names can't be empty".to_owned()));
+    }
+    if name.starts_with('.') || name.contains('/') || name.contains('\\') || name.contains('\0') {
+        return Err(Error::Keystore(format!("invalid key name {:?}", name)));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_dir_private(path: &PathBuf) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    try!(fs::DirBuilder::new().recursive(true).mode(0o700).create(path));
+    // in case it already existed with looser permissions
+    try!(fs::set_permissions(path, fs::Permissions::from_mode(0o700)));
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_dir_private(path: &PathBuf) -> Result<()> {
+    try!(fs::create_dir_all(path));
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_file_private(path: &PathBuf) -> io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fs::OpenOptions::new().write(true).create_new(true).mode(0o400).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_file_private(path: &PathBuf) -> io::Result<fs::File> {
+    fs::OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+impl Keystore {
+    /// A keystore in the given directory, which is created when the first key is added
+    pub fn new<P: Into<PathBuf>>(root: P) -> Keystore {
+        Keystore{ root: root.into() }
+    }
+
+    /// The keystore of the local ipfs repo
+    pub fn from_env() -> Keystore {
+        Keystore::new(get_ipfs_path().join("keystore"))
+    }
+
+    fn path_for(&self, name: &str) -> Result<PathBuf> {
+        try!(validate_name(name));
+        Ok(self.root.join(name))
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        match self.path_for(name) {
+            Ok(p) => fs::metadata(p).is_ok(),
+            Err(_) => false
+        }
+    }
+
+    /// Stores a key under a new name
+    ///
+    /// Existing keys are never overwritten; delete them first.
+    pub fn put(&mut self, name: &str, key: &PrivateKey) -> Result<()> {
+        let path = try!(self.path_for(name));
+        let bytes = try!(key.write_to_bytes());
+        try!(create_dir_private(&self.root));
+
+        let mut f = match create_file_private(&path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(Error::Keystore(format!("a key named {:?} already exists", name)));
+            }
+            Err(e) => return Err(Error::Io(e))
+        };
+        try!(f.write_all(&bytes));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<PrivateKey> {
+        let mut f = match fs::File::open(try!(self.path_for(name))) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::Keystore(format!("no key named {:?}", name)));
+            }
+            Err(e) => return Err(Error::Io(e))
+        };
+        let mut bytes = Vec::new();
+        try!(f.read_to_end(&mut bytes));
+
+        let mut key = PrivateKey::new();
+        try!(key.merge_from_bytes(&bytes).map_err(|e| Error::KeyParse(format!("{:?}", e))));
+        Ok(key)
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        match fs::remove_file(try!(self.path_for(name))) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(Error::Keystore(format!("no key named {:?}", name))),
+            Err(e) => Err(Error::Io(e))
+        }
+    }
+
+    /// Renames a key, failing if the new name is already taken
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let from_path = try!(self.path_for(from));
+        let to_path = try!(self.path_for(to));
+        // `fs::rename` would replace a key created under the new name in the meantime, but
+        // linking never overwrites anything
+        match fs::hard_link(&from_path, &to_path) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::Keystore(format!("no key named {:?}", from)));
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(Error::Keystore(format!("a key named {:?} already exists", to)));
+            }
+            Err(e) => return Err(Error::Io(e))
+        }
+        try!(fs::remove_file(from_path));
+        Ok(())
+    }
+
+    /// The names of every key, sorted
+    pub fn list(&self) -> Result<Vec<String>> {
+        let dir = match fs::read_dir(&self.root) {
+            Ok(d) => d,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::Io(e))
+        };
+        let mut names = Vec::new();
+        for entry in dir {
+            let entry = try!(entry);
+            if let Some(name) = entry.file_name().to_str() {
+                if validate_name(name).is_ok() {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Generates a new key and stores it, returning its peer ID
+    ///
+    /// `bits` is only used for RSA keys.
+    pub fn generate(&mut self, name: &str, key_type: KeyType, bits: usize) -> Result<PeerId> {
+        try!(validate_name(name));
+        let key = try!(keys::generate(key_type, bits));
+        try!(self.put(name, &key));
+        PeerId::from_private_key(&key)
+    }
+
+    /// Stores a key given in the base64 form used by the `PrivKey` field of the ipfs config
+    pub fn import(&mut self, name: &str, encoded: &str) -> Result<PeerId> {
+        let bytes = try!(encoded.from_base64());
+        let mut key = PrivateKey::new();
+        try!(key.merge_from_bytes(&bytes).map_err(|e| Error::KeyParse(format!("{:?}", e))));
+        let id = try!(PeerId::from_private_key(&key));
+        try!(self.put(name, &key));
+        Ok(id)
+    }
+
+    /// Returns a key in the base64 form used by the `PrivKey` field of the ipfs config
+    pub fn export(&self, name: &str) -> Result<String> {
+        let bytes = try!(try!(self.get(name)).write_to_bytes());
+        let cfg = Config{char_set: CharacterSet::Standard, newline: Newline::LF, pad: true, line_length: None};
+        Ok(bytes.to_base64(cfg))
+    }
+}
+
+
+#[test]
+fn test_keystore() {
+    let root = ::test_dir("keystore");
+    let mut ks = Keystore::new(root.clone());
+    assert_eq!(ks.list().unwrap(), Vec::<String>::new());
+
+    let id = ks.generate("self", KeyType::RSA, 1024).unwrap();
+    assert!(ks.has("self"));
+    assert_eq!(PeerId::from_private_key(&ks.get("self").unwrap()).unwrap(), id);
+    assert!(ks.generate("self", KeyType::Ed25519, 0).is_err());
+    let ed = ks.generate("ed", KeyType::Ed25519, 0).unwrap();
+    assert_eq!(ks.get("ed").unwrap().get_Type(), KeyType::Ed25519);
+    assert_eq!(PeerId::from_private_key(&ks.get("ed").unwrap()).unwrap(), ed);
+    ks.delete("ed").unwrap();
+
+    // export and import under another name
+    let exported = ks.export("self").unwrap();
+    assert_eq!(ks.import("copy", &exported).unwrap(), id);
+    assert_eq!(ks.list().unwrap(), vec!["copy".to_owned(), "self".to_owned()]);
+
+    ks.rename("copy", "backup").unwrap();
+    assert!(!ks.has("copy"));
+    assert_eq!(PeerId::from_private_key(&ks.get("backup").unwrap()).unwrap(), id);
+    match ks.rename("backup", "self") {
+        Err(Error::Keystore(_)) => {}
+        other => panic!("unexpected result {:?}", other)
+    }
+    assert!(ks.has("backup"));
+    assert_eq!(PeerId::from_private_key(&ks.get("self").unwrap()).unwrap(), id);
+    match ks.rename("missing", "other") {
+        Err(Error::Keystore(_)) => {}
+        other => panic!("unexpected result {:?}", other)
+    }
+    assert!(!ks.has("other"));
+
+    ks.delete("backup").unwrap();
+    assert!(ks.delete("backup").is_err());
+    assert!(ks.get("backup").is_err());
+    assert_eq!(ks.list().unwrap(), vec!["self".to_owned()]);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_keystore_names() {
+    let root = ::test_dir("keystore_names");
+    let mut ks = Keystore::new(root.clone());
+    let key = keys::generate_rsa(1024);
+    for name in &["", ".hidden", "a/b", "../escape"] {
+        match ks.put(name, &key) {
+            Err(Error::Keystore(_)) => {}
+            other => panic!("unexpected result for {:?}: {:?}", name, other)
+        }
+    }
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_keystore_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = ::test_dir("keystore_permissions");
+    let mut ks = Keystore::new(root.clone());
+    ks.generate("self", KeyType::Ed25519, 0).unwrap();
+
+    assert_eq!(fs::metadata(&root).unwrap().permissions().mode() & 0o777, 0o700);
+    assert_eq!(fs::metadata(root.join("self")).unwrap().permissions().mode() & 0o777, 0o400);
+    fs::remove_dir_all(&root).unwrap();
+}
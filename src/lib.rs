@@ -1,6 +1,3 @@
-#![feature(step_by)]
-#![feature(path_ext_deprecated)]
-
 extern crate rust_base58;
 extern crate rust_multihash;
 extern crate protobuf;
@@ -8,6 +5,8 @@ extern crate rustc_serialize;
 //extern crate asn1;
 //extern crate num;
 extern crate openssl;
+extern crate crypto as rust_crypto;
+extern crate rand;
 
 use std::path::PathBuf;
 use std::cell::RefCell;
@@ -23,9 +22,38 @@ pub mod merkledag;
 pub mod dht;
 pub mod crypto;
 
+pub mod error;
 pub mod multihash;
-
+pub mod multibase;
+pub mod varint;
+pub mod cid;
+pub mod blockstore;
+pub mod importer;
+pub mod reader;
+pub mod directory;
+pub mod path;
+pub mod walk;
+pub mod routing;
+pub mod codec;
+pub mod handler;
+pub mod lookup;
+pub mod simnet;
+pub mod keys;
+pub mod record;
+pub mod peer;
+pub mod keystore;
+pub mod config;
+pub mod multiaddr;
+pub mod pipe;
+pub mod multistream;
+pub mod transport;
+pub mod connmgr;
+pub mod secure;
+pub mod mux;
+
+pub use error::{Error, Result};
 use multihash::*;
+use blockstore::{Blockstore, FsBlockstore};
 
 
 /// A wrapper around a PBNode and multihash
@@ -43,33 +71,48 @@ pub struct Node {
 
 impl Node {
     /// Calculates hash from an actual object
-    pub fn from_pb(node: merkledag::PBNode) -> Node {
+    pub fn from_pb(node: merkledag::PBNode) -> Result<Node> {
         
-        let msg: Vec<u8> = node.write_to_bytes().unwrap();
-        let mh = multihash(HashTypes::SHA2256, msg).unwrap();
+        let msg: Vec<u8> = try!(node.write_to_bytes());
+        let mh = try!(hash_bytes(HashTypes::SHA2256, msg));
         let mh_str = mh.to_base58();
 
-        Node{ node: Some(node), mh_bytes: multihash::MultihashBytes(mh), mh_str: multihash::MultihashStr(mh_str)}
+        Ok(Node{ node: Some(node), mh_bytes: multihash::MultihashBytes(mh), mh_str: multihash::MultihashStr(mh_str)})
     }
 
-    pub fn from_mh<M: Multihash>(m: M) -> Node {
-        Node{node: None, mh_bytes: MultihashBytes(m.as_bytes()), mh_str: MultihashStr(m.base58())}
+    pub fn from_mh<M: Multihash>(m: M) -> Result<Node> {
+        Ok(Node{node: None, mh_bytes: MultihashBytes(try!(m.as_bytes())), mh_str: MultihashStr(m.base58())})
 
     }
 
     /// Load the given object from disk
-    pub fn load_from_disk(&mut self) {
-        use std::io::Read;
+    pub fn load_from_disk(&mut self) -> Result<()> {
+        self.load_from(&FsBlockstore::from_env())
+    }
+
+    /// Load the given object from a blockstore, checking that its contents match its hash
+    pub fn load_from<B: Blockstore>(&mut self, store: &B) -> Result<()> {
+        if self.node.is_none() {
+            let b = try!(store.get(&self.mh_bytes));
+            try!(self.set_node_bytes(&b));
+        }
+        Ok(())
+    }
+
+    /// Load the given object from a trusted blockstore, without rehashing it
+    pub fn load_from_unverified<B: Blockstore>(&mut self, store: &B) -> Result<()> {
         if self.node.is_none() {
-            // load the object from disk
-            let obj_path = get_blockfile_from_hash(&self.mh_bytes);
-            let mut f = std::fs::File::open(&obj_path).unwrap();
-            let mut b = Vec::new();
-            f.read_to_end(&mut b);
-            let mut pbn = merkledag::PBNode::new();
-            pbn.merge_from_bytes(&b);
-            self.node = Some(pbn);
+            let b = try!(store.get_unverified(&self.mh_bytes));
+            try!(self.set_node_bytes(&b));
         }
+        Ok(())
+    }
+
+    fn set_node_bytes(&mut self, b: &[u8]) -> Result<()> {
+        let mut pbn = merkledag::PBNode::new();
+        try!(pbn.merge_from_bytes(b));
+        self.node = Some(pbn);
+        Ok(())
     }
 
     pub fn get_node(&self) -> &merkledag::PBNode {
@@ -79,6 +122,15 @@ impl Node {
             panic!("This node should be loaded from disk first");
         }
     }
+
+    /// The size of this node plus the total size of everything it links to
+    ///
+    /// This is the value that goes in the `Tsize` of a link pointing at this node.
+    pub fn cumulative_size(&self) -> Result<u64> {
+        let pbnode = self.get_node();
+        let own_size = try!(pbnode.write_to_bytes()).len() as u64;
+        Ok(pbnode.get_Links().iter().fold(own_size, |acc, link| acc + link.get_Tsize()))
+    }
 }
 
 impl AsRef<multihash::MultihashBytes> for Node {
@@ -111,16 +163,40 @@ pub fn bin_to_hex(bin: &Vec<u8>) -> String {
 }
 
 // Takes a hex-encoded string (like a sha256 hash) and returns a vector or bytes
-pub fn hex_to_bin(hex: &str) -> Vec<u8> {
+pub fn hex_to_bin(hex: &str) -> Result<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return Err(Error::Hex(format!("odd number of digits in {:?}", hex)));
+    }
 
     let mut v = Vec::with_capacity(hex.len() / 2);
-    for x in (0..(hex.len()-1)).step_by(2) {
-        let s = &hex[x..x+2];
-        let n = u8::from_str_radix(s, 16).unwrap();
+    for pair in hex.as_bytes().chunks(2) {
+        let s = try!(std::str::from_utf8(pair).map_err(|_| Error::Hex(format!("non-ascii character in {:?}", hex))));
+        let n = try!(u8::from_str_radix(s, 16).map_err(|_| Error::Hex(format!("bad digits {:?} in {:?}", s, hex))));
         v.push(n);
     }
 
-    v
+    Ok(v)
+}
+
+/// A path for a test to create a directory at, which nothing else is using
+///
+/// The name includes the process ID and a counter, so tests running at the same time, in this
+/// process or another, never collide, and a failed earlier run can't leave anything in the way.
+#[cfg(test)]
+fn test_dir(name: &str) -> std::path::PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+    let dir = std::env::temp_dir().join(format!("ipfsrs_test_{}_{}_{}", name, std::process::id(), n));
+    // only possible if an earlier process with the same ID crashed
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Computes the multihash of some bytes
+pub fn hash_bytes(hash_type: HashTypes, data: Vec<u8>) -> Result<Vec<u8>> {
+    multihash(hash_type, data).map_err(|e| Error::Multihash(format!("{:?}", e)))
 }
 
 pub fn build_unixfs(s: &str) -> unixfs::Data {
@@ -136,8 +212,12 @@ pub fn build_unixfs(s: &str) -> unixfs::Data {
 fn test_hex_to_bin() {
     let s = "122070286b9afa6620a66f715c7020d68af3d10e1a497971629c07606bfdb812303d";
     let expected = vec![18, 32, 112, 40, 107, 154, 250, 102, 32, 166, 111, 113, 92, 112, 32, 214, 138, 243, 209, 14, 26, 73, 121, 113, 98, 156, 7, 96, 107, 253, 184, 18, 48, 61];
-    let b = hex_to_bin(s);
+    let b = hex_to_bin(s).unwrap();
     assert_eq!(b, expected);
+
+    assert!(hex_to_bin("123").is_err());
+    assert!(hex_to_bin("zz").is_err());
+    assert_eq!(hex_to_bin("").unwrap(), Vec::<u8>::new());
 }
 
 #[test]
@@ -148,61 +228,39 @@ fn test_bin_to_hex() {
 }
 
 /// Given a hex-encoded hash, return the IPFS_PATH that should contain this data
-pub fn get_blockfile_from_hash<M: multihash::Multihash>(hash: M) -> PathBuf {
-    use std::env;
-
-    let hex = hash.as_hex();
-    let mut ipfs_path: PathBuf = env::var("IPFS_PATH").and_then(|p| Ok(PathBuf::from(p))).unwrap_or_else(|_| { env::home_dir().unwrap().join(".ipfs")});
-
-    ipfs_path.push("blocks");
-    ipfs_path.push(&hex[0..8]);
-    ipfs_path.push(&hex);
-    ipfs_path.set_extension("data");
-
-    ipfs_path
+pub fn get_blockfile_from_hash<M: multihash::Multihash>(hash: M) -> Result<PathBuf> {
+    FsBlockstore::from_env().path_for(hash)
 }
 
 /// Writes a PBNode to disk to ~/.ipfs/blocks
-pub fn write_node_to_disk(node: &Node) {
-    use std::fs::PathExt;
+pub fn write_node_to_disk(node: &Node) -> Result<()> {
+    write_node(&mut FsBlockstore::from_env(), node)
+}
 
+/// Writes a PBNode to the given blockstore
+pub fn write_node<B: Blockstore>(store: &mut B, node: &Node) -> Result<()> {
     let pbnode = node.get_node();
-    let msg: Vec<u8> = pbnode.write_to_bytes().unwrap();
-
-    let path = get_blockfile_from_hash(&node.mh_bytes);
-
-    {
-        let d = path.parent().unwrap();
-        if !d.exists() {
-            std::fs::create_dir(&d).unwrap();
-        }
-
-        let mut f = std::fs::File::create(&path).unwrap();
-        pbnode.write_to_writer(&mut f).unwrap();
-    }
+    let msg: Vec<u8> = try!(pbnode.write_to_bytes());
 
+    store.put(&node.mh_bytes, &msg)
 }
 
 /// Given a base64-encoded key from an ipfs config file, produce a PKey
-pub fn read_privkey(privkey_str: &str) -> openssl::crypto::pkey::PKey {
-    let bytes = privkey_str.from_base64().unwrap();
+///
+/// See `config::Identity::private_key` for reading it straight out of a loaded config.
+pub fn read_privkey(privkey_str: &str) -> Result<openssl::crypto::pkey::PKey> {
+    let bytes = try!(privkey_str.from_base64());
     let mut privkey = crypto::PrivateKey::new();
-    privkey.merge_from_bytes(&bytes).unwrap();
-
-    let privkey_bytes: Vec<u8> = privkey.take_Data();
-    let mut pkey = openssl::crypto::pkey::PKey::new();
-    pkey.load_priv(&privkey_bytes);
-    pkey
+    try!(privkey.merge_from_bytes(&bytes).map_err(|e| Error::KeyParse(format!("{:?}", e))));
+    keys::private_pkey(&privkey)
 }
 
-pub fn get_pubkey_id(pkey: openssl::crypto::pkey::PKey) {
-    let pubkey = pkey.save_pub();
+/// The peer ID of an openssl RSA key
+pub fn get_pubkey_id(pkey: &openssl::crypto::pkey::PKey) -> Result<peer::PeerId> {
     let mut pubkey_pb = crypto::PublicKey::new();
-    pubkey_pb.set_Data(pubkey);
+    pubkey_pb.set_Data(pkey.save_pub());
     pubkey_pb.set_Type(crypto::KeyType::RSA);
-    let pubkey_bytes = pubkey_pb.write_to_bytes().unwrap();
-    let pubkey_mh = multihash(HashTypes::SHA2256, pubkey_bytes).unwrap();
-    let pubkey_encoded = &pubkey_mh.to_base58();
+    peer::PeerId::from_public_key(&pubkey_pb)
 }
 
 #[test]
@@ -0,0 +1,403 @@
+//! Iterative Kademlia lookups
+//!
+//! Starting from the closest peers we know about, we repeatedly ask the `alpha` closest peers we
+//! haven't asked yet for peers even closer to the target, until the `k` closest peers we have
+//! heard of have all answered.  `GET_VALUE` lookups stop early when a record turns up whose
+//! signature checks out; peers that hand back bad records are treated as failed.
+//!
+//! The lookup is written against the `Network` trait, so it can run over real connections or
+//! over the in-process `simnet::SimNetwork`.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crypto::PublicKey;
+use dht::{Message, Message_MessageType, Message_Peer, Record};
+use error::{Error, Result};
+use handler::target_key;
+use peer::PeerId;
+use record::verify_record;
+use routing::{Distance, Key};
+
+/// Something that can deliver a DHT request to a peer and wait for the reply
+pub trait Network {
+    /// Sends `msg` from `from` to `to`, returning the response
+    ///
+    /// If no response arrives within `timeout`, this should return `Error::Timeout`.
+    fn send(&mut self, from: &Message_Peer, to: &Message_Peer, msg: &Message, timeout: Duration) -> Result<Message>;
+
+    /// Sends `msg` from `from` to every peer in `to` at once, returning a result for each, in order
+    ///
+    /// The requests must be in flight together, so that the whole batch takes at most `timeout`
+    /// however many peers don't answer.
+    fn send_all(&mut self, from: &Message_Peer, to: &[Message_Peer], msg: &Message, timeout: Duration) -> Vec<Result<Message>>;
+}
+
+/// Tunable parameters of a lookup
+#[derive(Clone, Debug)]
+pub struct LookupConfig {
+    /// How many requests to have outstanding in each round
+    pub alpha: usize,
+    /// How many closest peers to look for
+    pub k: usize,
+    /// How long to wait for each peer
+    pub timeout: Duration,
+    /// Give up after this many rounds, even if we haven't converged
+    pub max_rounds: usize,
+    /// Public keys of record authors whose peer IDs don't have their key inlined
+    pub public_keys: HashMap<PeerId, PublicKey>,
+}
+
+impl Default for LookupConfig {
+    fn default() -> LookupConfig {
+        LookupConfig{ alpha: 3, k: 20, timeout: Duration::from_secs(10), max_rounds: 100, public_keys: HashMap::new() }
+    }
+}
+
+/// What a lookup found
+#[derive(Clone, Debug, Default)]
+pub struct LookupResult {
+    /// The closest peers that answered, closest first
+    pub closest: Vec<Message_Peer>,
+    /// For `GET_VALUE`, the record if one was found
+    pub record: Option<Record>,
+    /// For `GET_PROVIDERS`, every provider that was reported
+    pub providers: Vec<Message_Peer>,
+    /// The IDs of peers that failed or timed out, so the caller can drop them from its routing table
+    pub failed: Vec<String>,
+    /// How many requests were sent
+    pub queries: usize,
+    /// How many rounds of `alpha` requests it took
+    pub rounds: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PeerState {
+    NotQueried,
+    Succeeded,
+    Failed,
+}
+
+struct Candidate {
+    peer: Message_Peer,
+    distance: Distance,
+    state: PeerState,
+}
+
+/// Checks a record that a peer returned for `key`
+///
+/// The author's public key comes from its peer ID if it is inlined there, and from
+/// `config.public_keys` otherwise.
+fn check_record(record: &Record, key: &str, config: &LookupConfig) -> Result<()> {
+    if record.get_key() != key {
+        return Err(Error::BadRecord(format!("asked for {:?} but got a record for {:?}", key, record.get_key())));
+    }
+    let author = try!(PeerId::from_bytes(record.get_author()));
+    let pubkey = match author.public_key() {
+        Some(pubkey) => pubkey,
+        None => match config.public_keys.get(&author) {
+            Some(pubkey) => pubkey.clone(),
+            None => return Err(Error::BadRecord(format!("the public key of {} is unknown", author)))
+        }
+    };
+    verify_record(record, &pubkey)
+}
+
+/// Runs a lookup for `key` on behalf of `local`
+///
+/// `msg_type` must be `FIND_NODE`, `GET_VALUE` or `GET_PROVIDERS`.  `seeds` are the peers to
+/// start from, normally the closest peers in our own routing table.
+pub fn lookup<N: Network>(net: &mut N, local: &Message_Peer, msg_type: Message_MessageType, key: &str,
+                          seeds: Vec<Message_Peer>, config: &LookupConfig) -> Result<LookupResult> {
+    let target = try!(target_key(msg_type, key));
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let mut result = LookupResult::default();
+
+    let mut request = Message::new();
+    request.set_field_type(msg_type);
+    request.set_key(key.to_owned());
+
+    // returns true if the peer was new
+    fn add_candidate(candidates: &mut Vec<Candidate>, local: &Message_Peer, target: &Key, peer: Message_Peer) -> bool {
+        if peer.get_id() == local.get_id() || candidates.iter().any(|c| c.peer.get_id() == peer.get_id()) {
+            return false;
+        }
+        let distance = match Key::from_peer_id(peer.get_id()) {
+            Ok(k) => k.distance(target),
+            Err(_) => return false
+        };
+        candidates.push(Candidate{ peer: peer, distance: distance, state: PeerState::NotQueried });
+        true
+    }
+
+    for seed in seeds {
+        add_candidate(&mut candidates, local, &target, seed);
+    }
+
+    while result.rounds < config.max_rounds {
+        candidates.sort_by(|a, b| a.distance.cmp(&b.distance));
+
+        // the k closest peers that haven't failed
+        let closest: Vec<usize> = (0..candidates.len())
+            .filter(|&i| candidates[i].state != PeerState::Failed)
+            .take(config.k)
+            .collect();
+        let to_query: Vec<usize> = closest.iter().cloned()
+            .filter(|&i| candidates[i].state == PeerState::NotQueried)
+            .take(config.alpha)
+            .collect();
+        if to_query.is_empty() {
+            break;
+        }
+        result.rounds += 1;
+
+        // the whole round is sent at once, so unresponsive peers cost one timeout between them
+        let peers: Vec<Message_Peer> = to_query.iter().map(|&i| candidates[i].peer.clone()).collect();
+        result.queries += peers.len();
+        let responses = net.send_all(local, &peers, &request, config.timeout);
+
+        let mut learned = Vec::new();
+        for (&i, response) in to_query.iter().zip(responses) {
+            // a peer that hands back a bad record is no more use than one that doesn't answer,
+            // and we don't follow its suggestions either
+            let response = match response {
+                Ok(ref resp) if msg_type == Message_MessageType::GET_VALUE && resp.has_record()
+                                && check_record(resp.get_record(), key, config).is_err() => None,
+                Ok(resp) => Some(resp),
+                Err(_) => None
+            };
+            match response {
+                Some(resp) => {
+                    candidates[i].state = PeerState::Succeeded;
+                    learned.extend(resp.get_closerPeers().iter().cloned());
+                    if msg_type == Message_MessageType::GET_VALUE && resp.has_record() && result.record.is_none() {
+                        result.record = Some(resp.get_record().clone());
+                    }
+                    for provider in resp.get_providerPeers() {
+                        if !result.providers.iter().any(|p| p.get_id() == provider.get_id()) {
+                            result.providers.push(provider.clone());
+                        }
+                    }
+                }
+                None => {
+                    candidates[i].state = PeerState::Failed;
+                    result.failed.push(candidates[i].peer.get_id().to_owned());
+                }
+            }
+        }
+        for peer in learned {
+            add_candidate(&mut candidates, local, &target, peer);
+        }
+
+        if result.record.is_some() {
+            break;
+        }
+    }
+
+    candidates.sort_by(|a, b| a.distance.cmp(&b.distance));
+    result.closest = candidates.into_iter()
+        .filter(|c| c.state == PeerState::Succeeded)
+        .take(config.k)
+        .map(|c| c.peer)
+        .collect();
+    Ok(result)
+}
+
+/// Failure counts for peers across many lookups
+///
+/// Lookups report which peers failed; feed those in here and use `is_bad` to decide when a peer
+/// should be removed from the routing table.
+#[derive(Clone, Debug, Default)]
+pub struct FailureTracker {
+    failures: HashMap<String, usize>,
+    threshold: usize,
+}
+
+impl FailureTracker {
+    /// A tracker that considers a peer bad after `threshold` consecutive failures
+    pub fn new(threshold: usize) -> FailureTracker {
+        FailureTracker{ failures: HashMap::new(), threshold: threshold }
+    }
+
+    pub fn record_failure(&mut self, id: &str) {
+        *self.failures.entry(id.to_owned()).or_insert(0) += 1;
+    }
+
+    pub fn record_success(&mut self, id: &str) {
+        self.failures.remove(id);
+    }
+
+    pub fn is_bad(&self, id: &str) -> bool {
+        self.failures.get(id).map(|&n| n >= self.threshold).unwrap_or(false)
+    }
+
+    /// Records the outcome of a lookup
+    pub fn record_lookup(&mut self, result: &LookupResult) {
+        for id in &result.failed {
+            self.record_failure(id);
+        }
+        for peer in &result.closest {
+            self.record_success(peer.get_id());
+        }
+    }
+}
+
+
+#[cfg(test)]
+fn start(net: &::simnet::SimNetwork, i: usize, msg_type: Message_MessageType, key: &str) -> (Message_Peer, Vec<Message_Peer>) {
+    let node = net.node(&net.ids()[i]).unwrap();
+    let seeds = node.state.routing.closest_peers(&target_key(msg_type, key).unwrap(), 20);
+    (node.peer.clone(), seeds)
+}
+
+#[cfg(test)]
+fn ids(peers: &[Message_Peer]) -> Vec<String> {
+    peers.iter().map(|p| p.get_id().to_owned()).collect()
+}
+
+#[test]
+fn test_find_node_converges() {
+    use simnet::SimNetwork;
+
+    let mut net = SimNetwork::fully_connected(200, 20, 7);
+    let target = net.ids()[150].clone();
+    let (local, seeds) = start(&net, 0, Message_MessageType::FIND_NODE, &target);
+
+    let result = lookup(&mut net, &local, Message_MessageType::FIND_NODE, &target, seeds, &LookupConfig::default()).unwrap();
+    let expected: Vec<Message_Peer> = net.true_closest(&Key::from_peer_id(&target).unwrap(), 21).into_iter()
+        .filter(|p| p.get_id() != local.get_id())
+        .take(20)
+        .collect();
+    assert_eq!(ids(&result.closest), ids(&expected));
+    assert_eq!(result.closest[0].get_id(), target);
+    assert!(result.failed.is_empty());
+    assert!(result.rounds > 0);
+}
+
+#[test]
+fn test_find_node_with_churn() {
+    use simnet::SimNetwork;
+
+    let mut net = SimNetwork::fully_connected(200, 20, 8);
+    let all = net.ids().to_vec();
+    // take every fifth node offline, and remove a few others entirely
+    for (i, id) in all.iter().enumerate() {
+        if i % 5 == 4 {
+            net.node_mut(id).unwrap().online = false;
+        } else if i % 17 == 16 {
+            net.remove_node(id);
+        }
+    }
+    // and make a few more too slow to answer in time
+    for (i, id) in all.iter().enumerate() {
+        if i % 23 == 2 {
+            if let Some(node) = net.node_mut(id) {
+                node.latency = Duration::from_secs(60);
+            }
+        }
+    }
+
+    let target_id = all[100].clone();
+    let (local, seeds) = start(&net, 1, Message_MessageType::FIND_NODE, &target_id);
+    let result = lookup(&mut net, &local, Message_MessageType::FIND_NODE, &target_id, seeds, &LookupConfig::default()).unwrap();
+
+    assert_eq!(result.closest.len(), 20);
+    assert!(!result.failed.is_empty());
+    for p in &result.closest {
+        let node = net.node(p.get_id()).unwrap();
+        assert!(node.online);
+        assert!(node.latency < Duration::from_secs(10));
+        assert!(!result.failed.contains(&p.get_id().to_owned()));
+    }
+
+    let mut tracker = FailureTracker::new(1);
+    tracker.record_lookup(&result);
+    assert!(tracker.is_bad(&result.failed[0]));
+    assert!(!tracker.is_bad(result.closest[0].get_id()));
+}
+
+#[test]
+fn test_get_value_and_providers() {
+    use simnet::SimNetwork;
+
+    let mut net = SimNetwork::fully_connected(150, 20, 9);
+    use keys::ed25519_from_seed;
+    use record::make_signed_record;
+
+    let key = "/v/some-value";
+    let record = make_signed_record(key, b"the value".to_vec(), &ed25519_from_seed(&[5; 32])).unwrap();
+
+    let provider = net.node(&net.ids()[42]).unwrap().peer.clone();
+    let holders = net.true_closest(&target_key(Message_MessageType::GET_VALUE, key).unwrap(), 20);
+    for p in &holders {
+        let node = net.node_mut(p.get_id()).unwrap();
+        node.state.records.put(record.clone());
+        node.state.providers.add(key, provider.clone());
+    }
+
+    let far = net.ids().iter().position(|id| !holders.iter().any(|h| h.get_id() == &id[..])).unwrap();
+    let (local, seeds) = start(&net, far, Message_MessageType::GET_VALUE, key);
+    let result = lookup(&mut net, &local, Message_MessageType::GET_VALUE, key, seeds.clone(), &LookupConfig::default()).unwrap();
+    assert_eq!(result.record, Some(record));
+
+    let result = lookup(&mut net, &local, Message_MessageType::GET_PROVIDERS, key, seeds, &LookupConfig::default()).unwrap();
+    assert_eq!(ids(&result.providers), vec![provider.get_id().to_owned()]);
+}
+
+#[test]
+fn test_round_costs_one_timeout() {
+    use simnet::SimNetwork;
+
+    let mut net = SimNetwork::fully_connected(100, 20, 10);
+    let target = net.ids()[50].clone();
+    let (local, mut seeds) = start(&net, 0, Message_MessageType::FIND_NODE, &target);
+
+    // the three closest seeds are all asked in the first round, and none of them answer
+    let target_point = Key::from_peer_id(&target).unwrap();
+    seeds.sort_by_key(|p| Key::from_peer_id(p.get_id()).unwrap().distance(&target_point));
+    for p in &seeds[..3] {
+        net.node_mut(p.get_id()).unwrap().online = false;
+    }
+
+    let config = LookupConfig::default();
+    let result = lookup(&mut net, &local, Message_MessageType::FIND_NODE, &target, seeds.clone(), &config).unwrap();
+    assert_eq!(result.failed, ids(&seeds[..3]));
+    // one timeout for the first round, then 10ms for each round after it
+    assert_eq!(net.elapsed(), config.timeout + Duration::from_millis(10) * (result.rounds as u32 - 1));
+}
+
+#[test]
+fn test_forged_records_are_skipped() {
+    use keys::{ed25519_from_seed, generate_rsa, public_key};
+    use record::make_signed_record;
+    use simnet::SimNetwork;
+
+    let mut net = SimNetwork::fully_connected(100, 20, 11);
+    let key = "/v/signed";
+    let record = make_signed_record(key, b"the real value".to_vec(), &ed25519_from_seed(&[4; 32])).unwrap();
+    let mut forged = record.clone();
+    forged.set_value(b"a forgery".to_vec());
+
+    // the three closest holders hand out a forgery
+    let holders = net.true_closest(&target_key(Message_MessageType::GET_VALUE, key).unwrap(), 20);
+    for (i, p) in holders.iter().enumerate() {
+        let r = if i < 3 { forged.clone() } else { record.clone() };
+        net.node_mut(p.get_id()).unwrap().state.records.put(r);
+    }
+
+    let far = net.ids().iter().position(|id| !holders.iter().any(|h| h.get_id() == &id[..])).unwrap();
+    let local = net.node(&net.ids()[far]).unwrap().peer.clone();
+    let seeds = vec![holders[0].clone(), holders[1].clone(), holders[2].clone(), holders[10].clone()];
+    let result = lookup(&mut net, &local, Message_MessageType::GET_VALUE, key, seeds, &LookupConfig::default()).unwrap();
+    assert_eq!(result.record, Some(record));
+    assert_eq!(result.failed, ids(&holders[..3]));
+
+    // an RSA author's key isn't in its peer ID, so it has to be supplied
+    let rsa = generate_rsa(1024);
+    let rsa_record = make_signed_record(key, b"rsa".to_vec(), &rsa).unwrap();
+    let mut config = LookupConfig::default();
+    assert!(check_record(&rsa_record, key, &config).is_err());
+    config.public_keys.insert(PeerId::from_private_key(&rsa).unwrap(), public_key(&rsa).unwrap());
+    check_record(&rsa_record, key, &config).unwrap();
+    assert!(check_record(&rsa_record, "/v/other", &config).is_err());
+}
@@ -0,0 +1,328 @@
+//! Multiaddrs: self-describing network addresses
+//!
+//! A multiaddr is a sequence of protocols, each with an optional argument, written as
+//! `/ip4/1.2.3.4/tcp/4001/ipfs/Qm...`.  In binary, each protocol is a varint code followed by its
+//! argument, which is either fixed size or varint length-prefixed.  This is how peer addresses
+//! are carried in `dht::Message_Peer::addrs`.
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use protobuf::RepeatedField;
+
+use dht::Message_Peer;
+use error::{Error, Result};
+use peer::PeerId;
+use varint::{decode_varint, encode_varint};
+
+/// One component of a multiaddr
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Ip4(Ipv4Addr),
+    Ip6(Ipv6Addr),
+    Tcp(u16),
+    Udp(u16),
+    Dns(String),
+    Dns4(String),
+    Dns6(String),
+    /// Written `/ipfs/`, but `/p2p/` is accepted when parsing
+    Ipfs(PeerId),
+}
+
+impl Protocol {
+    /// The protocol's code in the binary form
+    pub fn code(&self) -> u64 {
+        match *self {
+            Protocol::Ip4(_) => 4,
+            Protocol::Ip6(_) => 41,
+            Protocol::Tcp(_) => 6,
+            Protocol::Udp(_) => 273,
+            Protocol::Dns(_) => 53,
+            Protocol::Dns4(_) => 54,
+            Protocol::Dns6(_) => 55,
+            Protocol::Ipfs(_) => 421,
+        }
+    }
+
+    /// The protocol's name in the string form
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Protocol::Ip4(_) => "ip4",
+            Protocol::Ip6(_) => "ip6",
+            Protocol::Tcp(_) => "tcp",
+            Protocol::Udp(_) => "udp",
+            Protocol::Dns(_) => "dns",
+            Protocol::Dns4(_) => "dns4",
+            Protocol::Dns6(_) => "dns6",
+            Protocol::Ipfs(_) => "ipfs",
+        }
+    }
+
+    fn from_str_parts(name: &str, arg: &str) -> Result<Protocol> {
+        let bad = || Error::Multiaddr(format!("invalid {} address {:?}", name, arg));
+        match name {
+            "ip4" => arg.parse().map(Protocol::Ip4).map_err(|_| bad()),
+            "ip6" => arg.parse().map(Protocol::Ip6).map_err(|_| bad()),
+            "tcp" => arg.parse().map(Protocol::Tcp).map_err(|_| bad()),
+            "udp" => arg.parse().map(Protocol::Udp).map_err(|_| bad()),
+            "dns" => Ok(Protocol::Dns(arg.to_owned())),
+            "dns4" => Ok(Protocol::Dns4(arg.to_owned())),
+            "dns6" => Ok(Protocol::Dns6(arg.to_owned())),
+            "ipfs" | "p2p" => arg.parse().map(Protocol::Ipfs).map_err(|_| bad()),
+            _ => Err(Error::Multiaddr(format!("unknown protocol {:?}", name)))
+        }
+    }
+
+    fn write_bytes(&self, buf: &mut Vec<u8>) {
+        encode_varint(self.code(), buf);
+        match *self {
+            Protocol::Ip4(ref ip) => buf.extend(ip.octets().iter()),
+            Protocol::Ip6(ref ip) => buf.extend(ip.octets().iter()),
+            Protocol::Tcp(port) | Protocol::Udp(port) => buf.extend(&[(port >> 8) as u8, port as u8]),
+            Protocol::Dns(ref s) | Protocol::Dns4(ref s) | Protocol::Dns6(ref s) => {
+                encode_varint(s.len() as u64, buf);
+                buf.extend(s.as_bytes());
+            }
+            Protocol::Ipfs(ref id) => {
+                let bytes = id.to_bytes();
+                encode_varint(bytes.len() as u64, buf);
+                buf.extend(bytes);
+            }
+        }
+    }
+
+    /// Decodes one protocol from the start of `buf`, returning it and how many bytes it used
+    fn from_bytes(buf: &[u8]) -> Result<(Protocol, usize)> {
+        let (code, mut used) = try!(decode_varint(buf));
+        let rest = &buf[used..];
+        let proto = match code {
+            4 => {
+                let b = try!(take_fixed(rest, code, 4));
+                used += 4;
+                Protocol::Ip4(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+            }
+            41 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(try!(take_fixed(rest, code, 16)));
+                used += 16;
+                Protocol::Ip6(Ipv6Addr::from(octets))
+            }
+            6 | 273 => {
+                let b = try!(take_fixed(rest, code, 2));
+                used += 2;
+                let port = (b[0] as u16) << 8 | b[1] as u16;
+                if code == 6 { Protocol::Tcp(port) } else { Protocol::Udp(port) }
+            }
+            53 | 54 | 55 => {
+                let (b, n) = try!(take_prefixed(rest, code));
+                used += n;
+                let name = try!(String::from_utf8(b.to_vec()).map_err(|_| Error::Multiaddr("DNS name isn't UTF-8".to_owned())));
+                match code {
+                    53 => Protocol::Dns(name),
+                    54 => Protocol::Dns4(name),
+                    _ => Protocol::Dns6(name),
+                }
+            }
+            421 => {
+                let (b, n) = try!(take_prefixed(rest, code));
+                used += n;
+                Protocol::Ipfs(try!(PeerId::from_bytes(b)))
+            }
+            _ => return Err(Error::Multiaddr(format!("unknown protocol code {}", code)))
+        };
+        Ok((proto, used))
+    }
+}
+
+/// The first `len` bytes of `rest`, the argument of protocol `code`
+fn take_fixed(rest: &[u8], code: u64, len: usize) -> Result<&[u8]> {
+    if rest.len() < len {
+        return Err(Error::Multiaddr(format!("protocol {} needs {} bytes but only {} are left", code, len, rest.len())));
+    }
+    Ok(&rest[..len])
+}
+
+/// A varint length-prefixed argument of protocol `code`, and the number of bytes it took up
+fn take_prefixed(rest: &[u8], code: u64) -> Result<(&[u8], usize)> {
+    let (len, n) = try!(decode_varint(rest));
+    let len = len as usize;
+    if rest.len() - n < len {
+        return Err(Error::Multiaddr(format!("protocol {} needs {} bytes but only {} are left", code, len, rest.len() - n)));
+    }
+    Ok((&rest[n..n + len], n + len))
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "/{}/", self.name()));
+        match *self {
+            Protocol::Ip4(ref ip) => write!(f, "{}", ip),
+            Protocol::Ip6(ref ip) => write!(f, "{}", ip),
+            Protocol::Tcp(port) | Protocol::Udp(port) => write!(f, "{}", port),
+            Protocol::Dns(ref s) | Protocol::Dns4(ref s) | Protocol::Dns6(ref s) => write!(f, "{}", s),
+            Protocol::Ipfs(ref id) => write!(f, "{}", id),
+        }
+    }
+}
+
+/// A parsed multiaddr
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Multiaddr {
+    protocols: Vec<Protocol>,
+}
+
+impl Multiaddr {
+    pub fn new(protocols: Vec<Protocol>) -> Multiaddr {
+        Multiaddr{ protocols: protocols }
+    }
+
+    /// Decodes the binary form
+    pub fn from_bytes(mut buf: &[u8]) -> Result<Multiaddr> {
+        let mut protocols = Vec::new();
+        while !buf.is_empty() {
+            let (proto, used) = try!(Protocol::from_bytes(buf));
+            protocols.push(proto);
+            buf = &buf[used..];
+        }
+        Ok(Multiaddr{ protocols: protocols })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for proto in &self.protocols {
+            proto.write_bytes(&mut buf);
+        }
+        buf
+    }
+
+    pub fn protocols(&self) -> &[Protocol] {
+        &self.protocols
+    }
+
+    /// Appends a protocol, like `/tcp/4001` to an `/ip4/...` address
+    pub fn push(&mut self, proto: Protocol) {
+        self.protocols.push(proto);
+    }
+
+    /// The peer ID at the end of an `/ipfs/` address, if there is one
+    pub fn peer_id(&self) -> Option<&PeerId> {
+        match self.protocols.last() {
+            Some(&Protocol::Ipfs(ref id)) => Some(id),
+            _ => None
+        }
+    }
+
+    /// This address without the trailing `/ipfs/` component, if there is one
+    pub fn without_peer_id(&self) -> Multiaddr {
+        let mut protocols = self.protocols.clone();
+        if self.peer_id().is_some() {
+            protocols.pop();
+        }
+        Multiaddr{ protocols: protocols }
+    }
+
+    /// Decodes every address of a DHT peer record
+    pub fn from_message_peer(peer: &Message_Peer) -> Result<Vec<Multiaddr>> {
+        peer.get_addrs().iter().map(|b| Multiaddr::from_bytes(b)).collect()
+    }
+
+    /// Replaces the addresses of a DHT peer record
+    pub fn set_message_peer_addrs(peer: &mut Message_Peer, addrs: &[Multiaddr]) {
+        peer.set_addrs(RepeatedField::from_vec(addrs.iter().map(|a| a.to_bytes()).collect()));
+    }
+
+    /// Adds an address to a DHT peer record
+    pub fn add_to_message_peer(&self, peer: &mut Message_Peer) {
+        peer.mut_addrs().push(self.to_bytes());
+    }
+}
+
+impl FromStr for Multiaddr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Multiaddr> {
+        if !s.starts_with('/') {
+            return Err(Error::Multiaddr(format!("{:?} doesn't start with /", s)));
+        }
+        let mut parts = s[1..].split('/').peekable();
+        let mut protocols = Vec::new();
+        while let Some(name) = parts.next() {
+            // allow one trailing slash
+            if name.is_empty() && parts.peek().is_none() && !protocols.is_empty() {
+                break;
+            }
+            let arg = match parts.next() {
+                Some(arg) => arg,
+                None => return Err(Error::Multiaddr(format!("protocol {:?} in {:?} is missing its argument", name, s)))
+            };
+            protocols.push(try!(Protocol::from_str_parts(name, arg)));
+        }
+        Ok(Multiaddr{ protocols: protocols })
+    }
+}
+
+impl fmt::Display for Multiaddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for proto in &self.protocols {
+            try!(write!(f, "{}", proto));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Multiaddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Multiaddr({})", self)
+    }
+}
+
+
+#[test]
+fn test_multiaddr_roundtrip() {
+    use ::{bin_to_hex, hex_to_bin};
+
+    for &(s, hex) in &[("/ip4/127.0.0.1/tcp/4001", "047f000001060fa1"),
+                       ("/ip6/::1/udp/1234", "2900000000000000000000000000000001910204d2"),
+                       ("/dns/example.com/tcp/443", "350b6578616d706c652e636f6d0601bb"),
+                       ("/dns4/example.com", "360b6578616d706c652e636f6d"),
+                       ("/ip4/104.131.131.82/tcp/4001/ipfs/QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ",
+                        "0468838352060fa1a503221220b04a57d40eca138809f139a76b12044333c3740391c9bf1ce9d8e21a79210bfd")] {
+        let addr: Multiaddr = s.parse().unwrap();
+        assert_eq!(addr.to_string(), s);
+        assert_eq!(bin_to_hex(&addr.to_bytes()), hex);
+        assert_eq!(Multiaddr::from_bytes(&hex_to_bin(hex).unwrap()).unwrap(), addr);
+    }
+}
+
+#[test]
+fn test_multiaddr_parsing() {
+    let addr: Multiaddr = "/ip4/104.131.131.82/tcp/4001/p2p/QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ/".parse().unwrap();
+    assert_eq!(addr.to_string(), "/ip4/104.131.131.82/tcp/4001/ipfs/QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ");
+    assert_eq!(addr.peer_id().unwrap().to_base58(), "QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ");
+    assert_eq!(addr.without_peer_id().to_string(), "/ip4/104.131.131.82/tcp/4001");
+    assert_eq!(addr.protocols()[1], Protocol::Tcp(4001));
+
+    for s in &["", "ip4/1.2.3.4", "/ip4", "/ip4/1.2.3.400", "/tcp/65536", "/foo/bar", "/ipfs/notbase58!"] {
+        assert!(s.parse::<Multiaddr>().is_err(), "{:?} should not parse", s);
+    }
+
+    // truncated arguments, and an unknown code
+    assert!(Multiaddr::from_bytes(&[4, 127, 0]).is_err());
+    assert!(Multiaddr::from_bytes(&[0x35, 10, b'a']).is_err());
+    assert!(Multiaddr::from_bytes(&[0x7f]).is_err());
+}
+
+#[test]
+fn test_message_peer_addrs() {
+    let a: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+    let b: Multiaddr = "/ip6/::1/tcp/4001".parse().unwrap();
+    let mut peer = Message_Peer::new();
+    Multiaddr::set_message_peer_addrs(&mut peer, &[a.clone()]);
+    b.add_to_message_peer(&mut peer);
+    assert_eq!(peer.get_addrs()[0], a.to_bytes());
+    assert_eq!(Multiaddr::from_message_peer(&peer).unwrap(), vec![a, b]);
+
+    peer.mut_addrs().push(vec![0xff]);
+    assert!(Multiaddr::from_message_peer(&peer).is_err());
+}
@@ -0,0 +1,370 @@
+//! Self-describing base encodings
+//!
+//! A multibase string is a single prefix character naming the encoding, followed by the encoded
+//! data.  For example `z` is base58btc and `b` is lowercase, unpadded base32.
+
+use rust_base58::{FromBase58, ToBase58};
+use rustc_serialize::base64::{ToBase64, FromBase64, Config, CharacterSet, Newline};
+
+use error::{Error, Result};
+use ::{bin_to_hex, hex_to_bin};
+
+const BASE32_LOWER: &'static [u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+const BASE32_UPPER: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const BASE36_LOWER: &'static [u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+const BASE36_UPPER: &'static [u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const BASE16_LOWER: &'static [u8] = b"0123456789abcdef";
+const BASE16_UPPER: &'static [u8] = b"0123456789ABCDEF";
+const BASE64_STANDARD: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64_URL: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// The supported encodings
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Base {
+    Base16,
+    Base16Upper,
+    Base32,
+    Base32Upper,
+    Base32Pad,
+    Base32PadUpper,
+    Base36,
+    Base36Upper,
+    Base58Btc,
+    Base64,
+    Base64Pad,
+    Base64Url,
+    Base64UrlPad,
+}
+
+impl Base {
+    /// The prefix character for this encoding
+    pub fn code(&self) -> char {
+        match *self {
+            Base::Base16 => 'f',
+            Base::Base16Upper => 'F',
+            Base::Base32 => 'b',
+            Base::Base32Upper => 'B',
+            Base::Base32Pad => 'c',
+            Base::Base32PadUpper => 'C',
+            Base::Base36 => 'k',
+            Base::Base36Upper => 'K',
+            Base::Base58Btc => 'z',
+            Base::Base64 => 'm',
+            Base::Base64Pad => 'M',
+            Base::Base64Url => 'u',
+            Base::Base64UrlPad => 'U',
+        }
+    }
+
+    /// Looks up an encoding by its prefix character
+    pub fn from_code(c: char) -> Option<Base> {
+        match c {
+            'f' => Some(Base::Base16),
+            'F' => Some(Base::Base16Upper),
+            'b' => Some(Base::Base32),
+            'B' => Some(Base::Base32Upper),
+            'c' => Some(Base::Base32Pad),
+            'C' => Some(Base::Base32PadUpper),
+            'k' => Some(Base::Base36),
+            'K' => Some(Base::Base36Upper),
+            'z' => Some(Base::Base58Btc),
+            'm' => Some(Base::Base64),
+            'M' => Some(Base::Base64Pad),
+            'u' => Some(Base::Base64Url),
+            'U' => Some(Base::Base64UrlPad),
+            _ => None
+        }
+    }
+
+    /// Encodes some bytes, without the prefix character
+    pub fn encode(&self, data: &[u8]) -> String {
+        match *self {
+            Base::Base16 => bin_to_hex(&data.to_vec()),
+            Base::Base16Upper => bin_to_hex(&data.to_vec()).to_uppercase(),
+            Base::Base32 => base32_encode(data, BASE32_LOWER, false),
+            Base::Base32Upper => base32_encode(data, BASE32_UPPER, false),
+            Base::Base32Pad => base32_encode(data, BASE32_LOWER, true),
+            Base::Base32PadUpper => base32_encode(data, BASE32_UPPER, true),
+            Base::Base36 => radix_encode(data, BASE36_LOWER),
+            Base::Base36Upper => radix_encode(data, BASE36_UPPER),
+            Base::Base58Btc => data.to_base58(),
+            Base::Base64 => data.to_base64(base64_config(CharacterSet::Standard, false)),
+            Base::Base64Pad => data.to_base64(base64_config(CharacterSet::Standard, true)),
+            Base::Base64Url => data.to_base64(base64_config(CharacterSet::UrlSafe, false)),
+            Base::Base64UrlPad => data.to_base64(base64_config(CharacterSet::UrlSafe, true)),
+        }
+    }
+
+    /// Decodes a string that doesn't have the prefix character
+    pub fn decode(&self, s: &str) -> Result<Vec<u8>> {
+        match *self {
+            Base::Base16 => {
+                try!(check_alphabet(s, BASE16_LOWER));
+                hex_to_bin(s)
+            }
+            Base::Base16Upper => {
+                try!(check_alphabet(s, BASE16_UPPER));
+                hex_to_bin(&s.to_lowercase())
+            }
+            Base::Base32 | Base::Base32Pad => base32_decode(s, BASE32_LOWER, *self == Base::Base32Pad),
+            Base::Base32Upper | Base::Base32PadUpper => base32_decode(s, BASE32_UPPER, *self == Base::Base32PadUpper),
+            Base::Base36 => radix_decode(s, BASE36_LOWER),
+            Base::Base36Upper => radix_decode(s, BASE36_UPPER),
+            Base::Base58Btc => Ok(try!(s.from_base58())),
+            Base::Base64 => base64_decode(s, BASE64_STANDARD, false),
+            Base::Base64Pad => base64_decode(s, BASE64_STANDARD, true),
+            Base::Base64Url => base64_decode(s, BASE64_URL, false),
+            Base::Base64UrlPad => base64_decode(s, BASE64_URL, true),
+        }
+    }
+}
+
+/// Encodes some bytes as a multibase string
+pub fn encode(base: Base, data: &[u8]) -> String {
+    let mut s = String::new();
+    s.push(base.code());
+    s.push_str(&base.encode(data));
+    s
+}
+
+/// Decodes a multibase string, returning the encoding that was used and the decoded bytes
+pub fn decode(s: &str) -> Result<(Base, Vec<u8>)> {
+    let mut chars = s.chars();
+    let c = try!(chars.next().ok_or_else(|| Error::Multibase("empty string".to_owned())));
+    let base = try!(Base::from_code(c).ok_or_else(|| Error::Multibase(format!("unknown prefix {:?}", c))));
+    let data = try!(base.decode(chars.as_str()));
+    Ok((base, data))
+}
+
+fn base64_config(char_set: CharacterSet, pad: bool) -> Config {
+    Config{char_set: char_set, newline: Newline::LF, pad: pad, line_length: None}
+}
+
+/// Checks that every character of `s` is in `alphabet`
+fn check_alphabet(s: &str, alphabet: &[u8]) -> Result<()> {
+    match s.chars().find(|&c| !(c.is_ascii() && alphabet.contains(&(c as u8)))) {
+        Some(c) => Err(Error::Multibase(format!("invalid character {:?} in {:?}", c, s))),
+        None => Ok(())
+    }
+}
+
+/// Splits `s` into its data and its trailing `=`s, checking there are exactly as many of those
+/// as a group of `group` characters needs
+fn split_padding(s: &str, group: usize) -> Result<(&str, usize)> {
+    let data = s.trim_right_matches('=');
+    let padding = s.len() - data.len();
+    if s.len() % group != 0 || padding >= group || (padding > 0 && data.len() % group == 0) {
+        return Err(Error::Multibase(format!("bad padding in {:?}", s)));
+    }
+    Ok((data, padding))
+}
+
+fn base64_decode(s: &str, alphabet: &[u8], pad: bool) -> Result<Vec<u8>> {
+    // rustc_serialize accepts both alphabets, and padding or not, so check those first
+    let data = if pad {
+        let (data, padding) = try!(split_padding(s, 4));
+        if padding > 2 {
+            return Err(Error::Multibase(format!("bad padding in {:?}", s)));
+        }
+        data
+    } else {
+        s
+    };
+    try!(check_alphabet(data, alphabet));
+    if data.len() % 4 == 1 {
+        return Err(Error::Multibase(format!("invalid base64 length in {:?}", s)));
+    }
+    Ok(try!(data.from_base64()))
+}
+
+fn base32_encode(data: &[u8], alphabet: &[u8], pad: bool) -> String {
+    let mut s = String::with_capacity((data.len() + 4) / 5 * 8);
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            s.push(alphabet[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        s.push(alphabet[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    if pad {
+        while s.len() % 8 != 0 {
+            s.push('=');
+        }
+    }
+    s
+}
+
+fn base32_decode(s: &str, alphabet: &[u8], pad: bool) -> Result<Vec<u8>> {
+    let trimmed = if pad {
+        let (data, padding) = try!(split_padding(s, 8));
+        // 1 to 4 leftover bytes take 2, 4, 5 or 7 characters
+        if ![0, 1, 3, 4, 6].contains(&padding) {
+            return Err(Error::Multibase(format!("bad padding in {:?}", s)));
+        }
+        data
+    } else {
+        s
+    };
+
+    let mut v = Vec::with_capacity(trimmed.len() * 5 / 8);
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+    for c in trimmed.bytes() {
+        let n = try!(alphabet.iter().position(|&a| a == c).ok_or_else(|| {
+            Error::Multibase(format!("invalid base32 character {:?} in {:?}", c as char, s))
+        }));
+        buffer = (buffer << 5) | n as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            v.push((buffer >> bits) as u8);
+        }
+    }
+    // whatever is left over must be padding bits
+    if bits >= 5 || buffer & ((1 << bits) - 1) != 0 {
+        return Err(Error::Multibase(format!("invalid base32 length in {:?}", s)));
+    }
+    Ok(v)
+}
+
+/// Treats the data as one big-endian number and writes it in the given base, with a leading
+/// `alphabet[0]` for every leading zero byte (the same scheme as base58)
+fn radix_encode(data: &[u8], alphabet: &[u8]) -> String {
+    let radix = alphabet.len() as u32;
+    let zeros = data.iter().take_while(|&&b| b == 0).count();
+
+    // little-endian digits in the target base
+    let mut digits: Vec<u32> = Vec::new();
+    for &byte in &data[zeros..] {
+        let mut carry = byte as u32;
+        for d in digits.iter_mut() {
+            carry += *d << 8;
+            *d = carry % radix;
+            carry /= radix;
+        }
+        while carry > 0 {
+            digits.push(carry % radix);
+            carry /= radix;
+        }
+    }
+
+    let mut s = String::with_capacity(zeros + digits.len());
+    for _ in 0..zeros {
+        s.push(alphabet[0] as char);
+    }
+    for &d in digits.iter().rev() {
+        s.push(alphabet[d as usize] as char);
+    }
+    s
+}
+
+fn radix_decode(s: &str, alphabet: &[u8]) -> Result<Vec<u8>> {
+    let radix = alphabet.len() as u32;
+    let zeros = s.bytes().take_while(|&c| c == alphabet[0]).count();
+
+    // little-endian bytes
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in s.bytes().skip(zeros) {
+        let mut carry = try!(alphabet.iter().position(|&a| a == c).ok_or_else(|| {
+            Error::Multibase(format!("invalid character {:?} in {:?}", c as char, s))
+        })) as u32;
+        for b in bytes.iter_mut() {
+            carry += (*b as u32) * radix;
+            *b = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+
+    let mut v = vec![0; zeros];
+    v.extend(bytes.into_iter().rev());
+    Ok(v)
+}
+
+
+#[test]
+fn test_multibase_vectors() {
+    let data = b"yes mani !";
+    let vectors = [(Base::Base16, "f796573206d616e692021"),
+                   (Base::Base16Upper, "F796573206D616E692021"),
+                   (Base::Base32, "bpfsxgidnmfxgsibb"),
+                   (Base::Base32Upper, "BPFSXGIDNMFXGSIBB"),
+                   (Base::Base32Pad, "cpfsxgidnmfxgsibb"),
+                   (Base::Base32PadUpper, "CPFSXGIDNMFXGSIBB"),
+                   (Base::Base36, "k2lcpzo5yikidynfl"),
+                   (Base::Base36Upper, "K2LCPZO5YIKIDYNFL"),
+                   (Base::Base58Btc, "z7paNL19xttacUY"),
+                   (Base::Base64, "meWVzIG1hbmkgIQ"),
+                   (Base::Base64Pad, "MeWVzIG1hbmkgIQ=="),
+                   (Base::Base64Url, "ueWVzIG1hbmkgIQ"),
+                   (Base::Base64UrlPad, "UeWVzIG1hbmkgIQ==")];
+
+    for &(base, expected) in vectors.iter() {
+        assert_eq!(encode(base, data), expected);
+        assert_eq!(decode(expected).unwrap(), (base, data.to_vec()));
+    }
+}
+
+#[test]
+fn test_multibase_leading_zeros() {
+    let data = b"\x00\x00yes mani !";
+    assert_eq!(encode(Base::Base36, data), "k002lcpzo5yikidynfl");
+    assert_eq!(encode(Base::Base32Pad, data), "caaahszltebwwc3tjeaqq====");
+    for base in &[Base::Base32, Base::Base32Pad, Base::Base36, Base::Base58Btc, Base::Base64] {
+        assert_eq!(decode(&encode(*base, data)).unwrap(), (*base, data.to_vec()));
+    }
+}
+
+#[test]
+fn test_multibase_errors() {
+    assert!(decode("").is_err());
+    assert!(decode("?abc").is_err());
+    assert!(decode("b1").is_err());
+    assert!(decode("bpfsxgidnmfxgsibb=").is_err());
+    assert!(decode("cpfsxgidnmfxgsib").is_err());
+    assert!(decode("k2lc!").is_err());
+}
+
+#[test]
+fn test_multibase_strict_alphabets() {
+    // each base only takes its own alphabet
+    assert!(decode("F796573206d616e692021").is_err());
+    assert!(decode("f796573206D616E692021").is_err());
+    assert!(decode("f+f").is_err());
+    assert!(decode("meWVzIG1h-mkgIQ").is_err());
+    assert!(decode("ueWVzIG1h+mkgIQ").is_err());
+    assert!(decode("ueWVzIG1h/mkgIQ").is_err());
+    assert!(decode("MeWVzIG1h_mkgIQ==").is_err());
+    assert!(decode("UeWVzIG1h+mkgIQ==").is_err());
+    assert!(decode("bPFSXGIDNMFXGSIBB").is_err());
+    assert!(decode("Bpfsxgidnmfxgsibb").is_err());
+}
+
+#[test]
+fn test_multibase_padding() {
+    assert!(decode("meWVzIG1hbmkgIQ==").is_err());
+    assert!(decode("MeWVzIG1hbmkgIQ").is_err());
+    assert!(decode("MeWVzIG1hbmkgIQ=").is_err());
+    assert!(decode("MeWVzIG1hbmkgIQ===").is_err());
+    assert!(decode("MeWVzIG1hbmkgIQ======").is_err());
+    assert!(decode("MeWVzIG1hbmkg====").is_err());
+    assert!(decode("MeWVz=IG1hbmkgIQ=").is_err());
+    assert!(decode("meWVzI").is_err());
+    assert_eq!(decode("MeWVzIG1hbmkgIQ==").unwrap().1, b"yes mani !".to_vec());
+
+    assert!(decode("cpfsxgidnmfxgsibb========").is_err());
+    assert!(decode("caaahszltebwwc3tjeaqq===").is_err());
+    assert!(decode("caaahszltebwwc3tjeaqq=====").is_err());
+    assert!(decode("caaahszltebwwc3tjeaq====").is_err());
+    assert!(decode("caaahs=zltebwwc3tjeaqq===").is_err());
+    assert_eq!(decode("caaahszltebwwc3tjeaqq====").unwrap().1, b"\x00\x00yes mani !".to_vec());
+}
@@ -1,25 +1,154 @@
 
+use std::fmt;
+use std::str::FromStr;
+
 use rust_base58::FromBase58;
 use rust_base58::ToBase58;
-use ::bin_to_hex;
+use rust_multihash::HashTypes;
+use ::{bin_to_hex, hex_to_bin};
+use error::{Error, Result};
+use multibase;
 
 /// The base58 encoding of a multihash
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MultihashStr(pub String);
 
 /// The raw multihash bytes
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MultihashBytes(pub Vec<u8>);
 
 /// The hex encoding of a multihash
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MultihashHex(pub String);
 
+/// A multihash that has been checked and split into its parts
+///
+/// Unlike the wrappers above, constructing one of these guarantees that the bytes really are a
+/// multihash: a hash function code, a length, and exactly that many bytes of digest.  Two values
+/// compare equal only if all three parts are equal, so these are suitable for use as map keys.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ParsedMultihash {
+    code: u8,
+    digest: Vec<u8>,
+}
+
+impl ParsedMultihash {
+    /// Parses raw multihash bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<ParsedMultihash> {
+        if bytes.len() < 2 {
+            return Err(Error::Multihash(format!("{} bytes is too short to be a multihash", bytes.len())));
+        }
+        let code = bytes[0];
+        let length = bytes[1] as usize;
+        let digest = &bytes[2..];
+        if digest.len() != length {
+            return Err(Error::Multihash(format!("declared length is {} but digest is {} bytes", length, digest.len())));
+        }
+
+        Ok(ParsedMultihash{ code: code, digest: digest.to_vec() })
+    }
+
+    /// Builds a multihash from a hash function code and a digest
+    pub fn new(code: u8, digest: Vec<u8>) -> Result<ParsedMultihash> {
+        if digest.len() > 255 {
+            return Err(Error::Multihash(format!("a {} byte digest is too long", digest.len())));
+        }
+        Ok(ParsedMultihash{ code: code, digest: digest })
+    }
+
+    /// Parses a multibase encoded multihash
+    pub fn from_multibase(s: &str) -> Result<ParsedMultihash> {
+        let (_, bytes) = try!(multibase::decode(s));
+        ParsedMultihash::from_bytes(&bytes)
+    }
+
+    /// Parses anything implementing `Multihash`
+    pub fn from_multihash<M: Multihash>(m: M) -> Result<ParsedMultihash> {
+        ParsedMultihash::from_bytes(&try!(m.as_bytes()))
+    }
+
+    /// The hash function code
+    pub fn code(&self) -> u8 {
+        self.code
+    }
+
+    /// The hash function, if it's one we know about
+    pub fn hash_type(&self) -> Option<HashTypes> {
+        HashTypes::from_u8(self.code)
+    }
+
+    /// The declared length of the digest
+    pub fn length(&self) -> u8 {
+        self.digest.len() as u8
+    }
+
+    /// The digest itself, without the code and length prefix
+    pub fn digest(&self) -> &[u8] {
+        &self.digest
+    }
+
+    /// The full multihash, including the code and length prefix
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(self.digest.len() + 2);
+        v.push(self.code);
+        v.push(self.digest.len() as u8);
+        v.extend(self.digest.iter().cloned());
+        v
+    }
+}
+
+impl FromStr for ParsedMultihash {
+    type Err = Error;
+
+    /// Parses a base58 encoded multihash
+    fn from_str(s: &str) -> Result<ParsedMultihash> {
+        ParsedMultihash::from_bytes(&try!(s.from_base58()))
+    }
+}
+
+impl fmt::Display for ParsedMultihash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_bytes().to_base58())
+    }
+}
+
+impl fmt::Debug for ParsedMultihash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ParsedMultihash({})", self)
+    }
+}
+
+impl MultihashStr {
+    pub fn parse(&self) -> Result<ParsedMultihash> {
+        let &MultihashStr(ref s) = self;
+        s.parse()
+    }
+}
+
+impl MultihashBytes {
+    pub fn parse(&self) -> Result<ParsedMultihash> {
+        let &MultihashBytes(ref b) = self;
+        ParsedMultihash::from_bytes(b)
+    }
+}
+
+impl MultihashHex {
+    pub fn parse(&self) -> Result<ParsedMultihash> {
+        let &MultihashHex(ref s) = self;
+        ParsedMultihash::from_bytes(&try!(hex_to_bin(s)))
+    }
+}
+
 /// 
 pub trait Multihash {
     fn base58(&self) -> String;
-    fn as_bytes(&self) -> Vec<u8>;
-    fn as_hex(&self) -> String;
+    fn as_bytes(&self) -> Result<Vec<u8>>;
+    fn as_hex(&self) -> Result<String>;
+
+    /// Encodes the multihash with the given multibase
+    fn to_multibase(&self, base: multibase::Base) -> Result<String> {
+        Ok(multibase::encode(base, &try!(self.as_bytes())))
+    }
 }
 
 
@@ -27,14 +156,13 @@ impl Multihash for MultihashStr {
     fn base58(&self) -> String {
         let &MultihashStr(ref s) = self; s.clone()
     }
-    fn as_bytes(&self) -> Vec<u8> {
+    fn as_bytes(&self) -> Result<Vec<u8>> {
         let &MultihashStr(ref s) = self;
-        s.from_base58().unwrap()
+        Ok(try!(s.from_base58()))
     }
-    fn as_hex(&self) -> String {
-        let &MultihashStr(ref s) = self;
-        let bytes = s.from_base58().unwrap();
-        bin_to_hex(&bytes)
+    fn as_hex(&self) -> Result<String> {
+        let bytes = try!(self.as_bytes());
+        Ok(bin_to_hex(&bytes))
     }
 }
 
@@ -43,13 +171,13 @@ impl Multihash for MultihashBytes {
         let &MultihashBytes(ref b) = self;
         b.to_base58()
     }
-    fn as_bytes(&self) -> Vec<u8> {
+    fn as_bytes(&self) -> Result<Vec<u8>> {
         let &MultihashBytes(ref b) = self;
-        b.clone()
+        Ok(b.clone())
     }
-    fn as_hex(&self) -> String {
+    fn as_hex(&self) -> Result<String> {
         let &MultihashBytes(ref b) = self;
-        bin_to_hex(b)
+        Ok(bin_to_hex(b))
     }
 }
 
@@ -58,10 +186,79 @@ impl<'a, T: Multihash> Multihash for &'a T {
     fn base58(&self) -> String {
        (*self).base58() 
     }
-    fn as_bytes(&self) -> Vec<u8> {
+    fn as_bytes(&self) -> Result<Vec<u8>> {
         (*self).as_bytes()
     }
-    fn as_hex(&self) -> String {
+    fn as_hex(&self) -> Result<String> {
         (*self).as_hex()
     }
 }
+
+impl Multihash for ParsedMultihash {
+    fn base58(&self) -> String {
+        self.to_bytes().to_base58()
+    }
+    fn as_bytes(&self) -> Result<Vec<u8>> {
+        Ok(self.to_bytes())
+    }
+    fn as_hex(&self) -> Result<String> {
+        Ok(bin_to_hex(&self.to_bytes()))
+    }
+}
+
+#[test]
+fn test_bad_base58() {
+    let mh = MultihashStr("QmNot0Base58".to_owned());
+    assert!(mh.as_bytes().is_err());
+    assert!(mh.as_hex().is_err());
+}
+
+#[test]
+fn test_parsed_multihash() {
+    let s = "QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe";
+    let mh: ParsedMultihash = s.parse().unwrap();
+    assert_eq!(mh.code(), 0x12);
+    assert_eq!(mh.length(), 32);
+    assert_eq!(mh.digest().len(), 32);
+    assert_eq!(mh.to_string(), s);
+
+    let hex = MultihashHex("122070286b9afa6620a66f715c7020d68af3d10e1a497971629c07606bfdb812303d".to_owned());
+    assert_eq!(hex.parse().unwrap(), mh);
+    assert_eq!(MultihashStr(s.to_owned()).parse().unwrap(), mh);
+    assert_eq!(MultihashBytes(mh.to_bytes()).parse().unwrap(), mh);
+    assert_eq!(ParsedMultihash::from_multihash(&mh).unwrap(), mh);
+}
+
+#[test]
+fn test_parsed_multihash_rejects_bad_lengths() {
+    // truncated digest
+    assert!(MultihashHex("12207028".to_owned()).parse().is_err());
+    // digest longer than declared
+    assert!(MultihashHex("1202aabbcc".to_owned()).parse().is_err());
+    // no length byte at all
+    assert!(ParsedMultihash::from_bytes(&[0x12]).is_err());
+    assert!(ParsedMultihash::from_bytes(&[]).is_err());
+}
+
+#[test]
+fn test_parsed_multihash_ordering() {
+    use std::collections::HashSet;
+
+    let a = ParsedMultihash::from_bytes(&[0x12, 2, 0, 1]).unwrap();
+    let b = ParsedMultihash::from_bytes(&[0x12, 2, 0, 2]).unwrap();
+    assert!(a < b);
+
+    let mut set = HashSet::new();
+    set.insert(a.clone());
+    set.insert(ParsedMultihash::from_bytes(&[0x12, 2, 0, 1]).unwrap());
+    assert_eq!(set.len(), 1);
+}
+
+#[test]
+fn test_multihash_multibase() {
+    let mh = MultihashStr("QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe".to_owned());
+    assert_eq!(mh.to_multibase(multibase::Base::Base58Btc).unwrap(), "zQmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe");
+
+    let b32 = mh.to_multibase(multibase::Base::Base32).unwrap();
+    assert_eq!(ParsedMultihash::from_multibase(&b32).unwrap(), mh.parse().unwrap());
+}
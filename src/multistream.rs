@@ -0,0 +1,244 @@
+//! multistream-select protocol negotiation
+//!
+//! Both sides start by sending the `/multistream/1.0.0` header.  The dialer then proposes
+//! protocols one at a time; the listener echoes back a protocol it supports, or answers `na`.
+//! The dialer can also send `ls` to get the listener's list of protocols.
+//!
+//! Every message is a protocol ID followed by a newline, prefixed with its length (including
+//! the newline) as a varint.
+
+use std::io::{Read, Write};
+
+use codec::{read_frame, write_frame};
+use error::{Error, Result};
+
+/// The multistream-select protocol ID, sent as the header
+pub const PROTOCOL_ID: &'static str = "/multistream/1.0.0";
+
+/// The listener's answer to a protocol it doesn't support
+pub const NA: &'static str = "na";
+
+/// The request for the listener's protocols
+pub const LS: &'static str = "ls";
+
+/// The largest message we accept, which bounds the size of an `ls` response
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+fn delimited(msg: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(msg.len() + 1);
+    buf.extend(msg);
+    buf.push(b'\n');
+    buf
+}
+
+fn undelimited(mut frame: Vec<u8>) -> Result<Vec<u8>> {
+    if frame.pop() != Some(b'\n') {
+        return Err(Error::Multistream("message isn't newline terminated".to_owned()));
+    }
+    Ok(frame)
+}
+
+/// Writes one message
+pub fn write_message<W: Write>(w: &mut W, msg: &str) -> Result<()> {
+    write_frame(w, &delimited(msg.as_bytes()), MAX_MESSAGE_SIZE)
+}
+
+/// Reads one message, without its newline
+pub fn read_message<R: Read>(r: &mut R) -> Result<String> {
+    let frame = match try!(read_frame(r, MAX_MESSAGE_SIZE)) {
+        Some(frame) => frame,
+        None => return Err(Error::Multistream("the stream ended during negotiation".to_owned()))
+    };
+    String::from_utf8(try!(undelimited(frame))).map_err(|_| Error::Multistream("message isn't UTF-8".to_owned()))
+}
+
+/// Sends our header and checks the other side's
+pub fn handshake<S: Read + Write>(s: &mut S) -> Result<()> {
+    try!(write_message(s, PROTOCOL_ID));
+    let header = try!(read_message(s));
+    if header != PROTOCOL_ID {
+        return Err(Error::Multistream(format!("expected the header {:?} but got {:?}", PROTOCOL_ID, header)));
+    }
+    Ok(())
+}
+
+/// Proposes one protocol, after the handshake
+///
+/// Returns whether the listener accepted it.
+pub fn select<S: Read + Write>(s: &mut S, protocol: &str) -> Result<bool> {
+    try!(write_message(s, protocol));
+    let resp = try!(read_message(s));
+    if resp == protocol {
+        Ok(true)
+    } else if resp == NA {
+        Ok(false)
+    } else {
+        Err(Error::Multistream(format!("proposed {:?} but the listener answered {:?}", protocol, resp)))
+    }
+}
+
+/// Does the handshake, then proposes each protocol in turn until one is accepted
+///
+/// Returns the accepted protocol.  Afterwards the stream belongs to that protocol.
+pub fn select_one_of<S: Read + Write>(s: &mut S, protocols: &[&str]) -> Result<String> {
+    try!(handshake(s));
+    for protocol in protocols {
+        if try!(select(s, protocol)) {
+            return Ok((*protocol).to_owned());
+        }
+    }
+    Err(Error::Multistream(format!("the listener supports none of {:?}", protocols)))
+}
+
+/// Asks the listener for its protocols, after the handshake
+pub fn ls<S: Read + Write>(s: &mut S) -> Result<Vec<String>> {
+    try!(write_message(s, LS));
+    let frame = match try!(read_frame(s, MAX_MESSAGE_SIZE)) {
+        Some(frame) => frame,
+        None => return Err(Error::Multistream("the stream ended during negotiation".to_owned()))
+    };
+    let list = try!(undelimited(frame));
+    let mut list = &list[..];
+
+    let mut protocols = Vec::new();
+    while !list.is_empty() {
+        protocols.push(try!(read_message(&mut list)));
+    }
+    Ok(protocols)
+}
+
+/// The listening side: a list of supported protocols
+#[derive(Clone, Debug, Default)]
+pub struct Listener {
+    protocols: Vec<String>,
+}
+
+impl Listener {
+    pub fn new() -> Listener {
+        Listener{ protocols: Vec::new() }
+    }
+
+    pub fn add(&mut self, protocol: &str) {
+        if !self.supports(protocol) {
+            self.protocols.push(protocol.to_owned());
+        }
+    }
+
+    pub fn supports(&self, protocol: &str) -> bool {
+        self.protocols.iter().any(|p| p == protocol)
+    }
+
+    pub fn protocols(&self) -> &[String] {
+        &self.protocols
+    }
+
+    /// Does the handshake, then answers `ls` requests and proposals until the dialer proposes a
+    /// protocol we support
+    ///
+    /// Returns the agreed protocol.  Afterwards the stream belongs to that protocol.
+    pub fn negotiate<S: Read + Write>(&self, s: &mut S) -> Result<String> {
+        try!(handshake(s));
+        loop {
+            let msg = try!(read_message(s));
+            if msg == LS {
+                let mut list = Vec::new();
+                for p in &self.protocols {
+                    try!(write_message(&mut list, p));
+                }
+                try!(write_frame(s, &delimited(&list), MAX_MESSAGE_SIZE));
+            } else if self.supports(&msg) {
+                try!(write_message(s, &msg));
+                return Ok(msg);
+            } else {
+                try!(write_message(s, NA));
+            }
+        }
+    }
+}
+
+
+#[test]
+fn test_wire_format() {
+    let mut buf = Vec::new();
+    write_message(&mut buf, PROTOCOL_ID).unwrap();
+    assert_eq!(buf, b"\x13/multistream/1.0.0\n".to_vec());
+    assert_eq!(read_message(&mut &buf[..]).unwrap(), PROTOCOL_ID);
+
+    assert!(read_message(&mut &b"\x02na"[..]).is_err());
+    assert!(read_message(&mut &b""[..]).is_err());
+}
+
+#[cfg(test)]
+fn test_listener() -> Listener {
+    let mut listener = Listener::new();
+    listener.add("/ipfs/bitswap/1.0.0");
+    listener.add("/ipfs/kad/1.0.0");
+    listener
+}
+
+#[test]
+fn test_negotiate() {
+    use std::thread;
+    use pipe::pipe;
+
+    let (mut dialer, mut listener_end) = pipe();
+    let t = thread::spawn(move || {
+        let proto = test_listener().negotiate(&mut listener_end).unwrap();
+        // the stream now carries the chosen protocol
+        let mut buf = [0; 5];
+        listener_end.read_exact(&mut buf).unwrap();
+        (proto, buf)
+    });
+
+    let proto = select_one_of(&mut dialer, &["/ipfs/id/1.0.0", "/ipfs/kad/1.0.0"]).unwrap();
+    assert_eq!(proto, "/ipfs/kad/1.0.0");
+    dialer.write_all(b"hello").unwrap();
+
+    let (proto, buf) = t.join().unwrap();
+    assert_eq!(proto, "/ipfs/kad/1.0.0");
+    assert_eq!(&buf, b"hello");
+}
+
+#[test]
+fn test_ls() {
+    use std::thread;
+    use pipe::pipe;
+
+    let (mut dialer, mut listener_end) = pipe();
+    let t = thread::spawn(move || test_listener().negotiate(&mut listener_end));
+
+    handshake(&mut dialer).unwrap();
+    assert_eq!(ls(&mut dialer).unwrap(), vec!["/ipfs/bitswap/1.0.0".to_owned(), "/ipfs/kad/1.0.0".to_owned()]);
+    assert!(!select(&mut dialer, "/ipfs/id/1.0.0").unwrap());
+    assert!(select(&mut dialer, "/ipfs/bitswap/1.0.0").unwrap());
+    assert_eq!(t.join().unwrap().unwrap(), "/ipfs/bitswap/1.0.0");
+}
+
+#[test]
+fn test_no_common_protocol() {
+    use std::thread;
+    use pipe::pipe;
+
+    let (mut dialer, mut listener_end) = pipe();
+    let t = thread::spawn(move || test_listener().negotiate(&mut listener_end));
+
+    assert!(select_one_of(&mut dialer, &["/ipfs/id/1.0.0", "/ipfs/ping/1.0.0"]).is_err());
+    // hanging up leaves the listener without a protocol
+    drop(dialer);
+    assert!(t.join().unwrap().is_err());
+}
+
+#[test]
+fn test_bad_header() {
+    use std::thread;
+    use pipe::pipe;
+
+    let (mut dialer, mut listener_end) = pipe();
+    let t = thread::spawn(move || test_listener().negotiate(&mut listener_end));
+
+    write_message(&mut dialer, "/multistream/0.0.1").unwrap();
+    match t.join().unwrap() {
+        Err(Error::Multistream(ref s)) => assert!(s.contains("header")),
+        other => panic!("unexpected result {:?}", other)
+    }
+}
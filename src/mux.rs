@@ -0,0 +1,806 @@
+//! A stream multiplexer, using yamux framing
+//!
+//! Many independent, bidirectional substreams share one underlying connection.  Every frame
+//! starts with a 12 byte header: version (0), type, flags, stream ID and length, the last three
+//! big-endian.  `Data` frames carry `length` bytes of payload; `WindowUpdate` frames grant the
+//! other side `length` more bytes of credit on a stream.  The `SYN`, `ACK`, `FIN` and `RST` flags
+//! open, acknowledge, half-close and abort a stream.
+//!
+//! Each stream starts with `INITIAL_WINDOW` bytes of credit in each direction, and a writer blocks
+//! once it has used up its credit, until the reader catches up and sends a window update.  So a
+//! slow reader on one stream never holds up the others.
+//!
+//! A `Session` reads frames on a background thread, so it needs the connection split into a
+//! reading half and a writing half, like `TcpStream::try_clone` or `PipeEnd::split` give.  The
+//! thread runs until either side closes the session, or the connection ends.  The writing half
+//! must implement `Shutdown`, so that closing the session can close the whole connection and
+//! wake the thread up, whatever the other side does.
+
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::io::{Read, Write};
+use std::net;
+use std::net::TcpStream;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+
+use error::{Error, Result};
+use multistream;
+use pipe::PipeWriter;
+
+/// The protocol ID to negotiate with multistream-select
+pub const PROTOCOL_ID: &'static str = "/yamux/1.0.0";
+
+/// How many bytes each side may send on a new stream before it needs a window update
+pub const INITIAL_WINDOW: u32 = 256 * 1024;
+
+/// The most payload we put in one `Data` frame
+pub const MAX_FRAME_DATA: usize = 16 * 1024;
+
+const HEADER_LEN: usize = 12;
+const VERSION: u8 = 0;
+
+const TYPE_DATA: u8 = 0;
+const TYPE_WINDOW_UPDATE: u8 = 1;
+const TYPE_PING: u8 = 2;
+const TYPE_GO_AWAY: u8 = 3;
+
+const FLAG_SYN: u16 = 1;
+const FLAG_ACK: u16 = 2;
+const FLAG_FIN: u16 = 4;
+const FLAG_RST: u16 = 8;
+
+const GO_AWAY_NORMAL: u32 = 0;
+const GO_AWAY_PROTOCOL_ERROR: u32 = 1;
+
+/// Which end of the connection we are; clients use odd stream IDs and servers even ones
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Client,
+    Server,
+}
+
+/// A connection that can be closed in both directions through its writing half
+///
+/// This must wake up any read blocked on the reading half, which then sees end-of-file or an
+/// error.
+pub trait Shutdown {
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl Shutdown for TcpStream {
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, net::Shutdown::Both)
+    }
+}
+
+impl Shutdown for PipeWriter {
+    fn shutdown(&self) -> io::Result<()> {
+        PipeWriter::shutdown(self);
+        Ok(())
+    }
+}
+
+/// The writing half of a connection, as the session keeps it
+trait WriteHalf: Write + Shutdown {}
+
+impl<W: Write + Shutdown> WriteHalf for W {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Header {
+    kind: u8,
+    flags: u16,
+    stream_id: u32,
+    length: u32,
+}
+
+impl Header {
+    fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut buf = [0; HEADER_LEN];
+        buf[0] = VERSION;
+        buf[1] = self.kind;
+        buf[2] = (self.flags >> 8) as u8;
+        buf[3] = self.flags as u8;
+        for i in 0..4 {
+            buf[4 + i] = (self.stream_id >> (24 - 8 * i)) as u8;
+            buf[8 + i] = (self.length >> (24 - 8 * i)) as u8;
+        }
+        buf
+    }
+
+    fn decode(buf: &[u8; HEADER_LEN]) -> Result<Header> {
+        if buf[0] != VERSION {
+            return Err(Error::Mux(format!("unsupported version {}", buf[0])));
+        }
+        let mut stream_id = 0;
+        let mut length = 0;
+        for i in 0..4 {
+            stream_id = (stream_id << 8) | buf[4 + i] as u32;
+            length = (length << 8) | buf[8 + i] as u32;
+        }
+        Ok(Header{ kind: buf[1], flags: (buf[2] as u16) << 8 | buf[3] as u16, stream_id: stream_id, length: length })
+    }
+}
+
+/// Our view of one stream
+struct StreamState {
+    /// Data received but not yet read
+    buf: Vec<u8>,
+    pos: usize,
+    /// How much more the other side may send us
+    recv_window: u32,
+    /// How much has been read since we last sent a window update
+    unacked: u32,
+    /// How much more we may send
+    send_window: u32,
+    /// We sent a FIN
+    local_closed: bool,
+    /// The other side sent a FIN
+    remote_closed: bool,
+    /// Either side sent a RST
+    reset: bool,
+}
+
+impl StreamState {
+    fn new() -> StreamState {
+        StreamState{ buf: Vec::new(), pos: 0, recv_window: INITIAL_WINDOW, unacked: 0, send_window: INITIAL_WINDOW,
+                     local_closed: false, remote_closed: false, reset: false }
+    }
+
+    /// Copies buffered data into `buf`, returning `None` if we need to wait for more
+    ///
+    /// Alongside the count, returns the size of the window update to send, if one is due.
+    fn try_read(&mut self, buf: &mut [u8], session_closed: bool) -> Option<io::Result<(usize, Option<u32>)>> {
+        if self.pos < self.buf.len() {
+            let n = cmp::min(buf.len(), self.buf.len() - self.pos);
+            buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+            self.pos += n;
+            if self.pos == self.buf.len() {
+                self.buf.clear();
+                self.pos = 0;
+            }
+            // batch up window updates rather than sending one per read
+            self.unacked += n as u32;
+            let mut update = None;
+            if self.unacked >= INITIAL_WINDOW / 2 && !self.remote_closed && !self.reset {
+                self.recv_window += self.unacked;
+                update = Some(self.unacked);
+                self.unacked = 0;
+            }
+            return Some(Ok((n, update)));
+        }
+        if self.reset {
+            return Some(Err(io::Error::new(io::ErrorKind::ConnectionReset, "the stream was reset")));
+        }
+        if self.remote_closed {
+            return Some(Ok((0, None)));
+        }
+        if session_closed {
+            return Some(Err(io::Error::new(io::ErrorKind::ConnectionAborted, "the session ended")));
+        }
+        None
+    }
+
+    /// Takes up to `len` bytes of send credit, returning `None` if we need to wait for more
+    fn try_reserve(&mut self, len: usize, session_closed: bool) -> Option<io::Result<usize>> {
+        if self.reset {
+            return Some(Err(io::Error::new(io::ErrorKind::ConnectionReset, "the stream was reset")));
+        }
+        if self.local_closed {
+            return Some(Err(io::Error::new(io::ErrorKind::BrokenPipe, "the stream is closed for writing")));
+        }
+        if session_closed {
+            return Some(Err(io::Error::new(io::ErrorKind::ConnectionAborted, "the session ended")));
+        }
+        if self.send_window == 0 {
+            return None;
+        }
+        let n = cmp::min(cmp::min(len, self.send_window as usize), MAX_FRAME_DATA);
+        self.send_window -= n as u32;
+        Some(Ok(n))
+    }
+}
+
+struct Shared {
+    streams: HashMap<u32, StreamState>,
+    /// Streams the other side opened that haven't been accepted yet
+    incoming: VecDeque<u32>,
+    /// The ID we will give the next stream we open
+    next_id: u32,
+    /// The highest ID the other side has opened, which new ones must be above
+    last_remote_id: u32,
+    closed: bool,
+}
+
+/// State shared by the session, its streams, and the reading thread
+struct Inner {
+    shared: Mutex<Shared>,
+    /// Signalled whenever anything in `shared` changes
+    changed: Condvar,
+    /// `None` once the session is closed
+    writer: Mutex<Option<Box<WriteHalf + Send>>>,
+}
+
+impl Inner {
+    fn send(&self, header: Header, data: &[u8]) -> Result<()> {
+        write_frame(&mut self.writer.lock().unwrap(), header, data)
+    }
+
+    fn send_flag(&self, stream_id: u32, flags: u16, delta: u32) -> Result<()> {
+        self.send(Header{ kind: TYPE_WINDOW_UPDATE, flags: flags, stream_id: stream_id, length: delta }, &[])
+    }
+
+    /// Ends the session: sends `GoAway` with `code`, if we still can, and shuts down the connection
+    fn shut_down(&self, code: u32) {
+        // marked first, so that by the time the other side sees the connection close, we
+        // already refuse new streams
+        self.shared.lock().unwrap().closed = true;
+        self.changed.notify_all();
+        let _ = self.send(Header{ kind: TYPE_GO_AWAY, flags: 0, stream_id: 0, length: code }, &[]);
+        if let Some(w) = self.writer.lock().unwrap().take() {
+            let _ = w.shutdown();
+        }
+    }
+
+    /// Applies a `Data` or `WindowUpdate` frame, returning the acknowledgement to send if it opened a stream
+    fn handle_stream_frame(&self, header: &Header, data: Vec<u8>) -> Result<Option<Header>> {
+        let mut shared = self.shared.lock().unwrap();
+        let mut reply = None;
+        if header.flags & FLAG_SYN != 0 {
+            // the other side's IDs have the opposite parity to ours, and only go up, so they can
+            // never collide with ours or with streams that have come and gone
+            let id = header.stream_id;
+            if id == 0 || id % 2 == shared.next_id % 2 {
+                return Err(Error::Mux(format!("the other side opened stream {}, which isn't one of its IDs", id)));
+            }
+            if id <= shared.last_remote_id {
+                return Err(Error::Mux(format!("the other side reused stream ID {}", id)));
+            }
+            shared.last_remote_id = id;
+            shared.streams.insert(header.stream_id, StreamState::new());
+            shared.incoming.push_back(header.stream_id);
+            reply = Some(Header{ kind: TYPE_WINDOW_UPDATE, flags: FLAG_ACK, stream_id: header.stream_id, length: 0 });
+        }
+
+        // frames for streams we have already dropped are ignored
+        if let Some(st) = shared.streams.get_mut(&header.stream_id) {
+            if header.kind == TYPE_DATA {
+                if data.len() as u32 > st.recv_window {
+                    return Err(Error::Mux(format!("stream {} sent more than its window allows", header.stream_id)));
+                }
+                st.recv_window -= data.len() as u32;
+                st.buf.extend_from_slice(&data);
+            } else {
+                st.send_window = st.send_window.saturating_add(header.length);
+            }
+            if header.flags & FLAG_FIN != 0 {
+                st.remote_closed = true;
+            }
+            if header.flags & FLAG_RST != 0 {
+                st.reset = true;
+            }
+        }
+        self.changed.notify_all();
+        Ok(reply)
+    }
+}
+
+/// Writes a frame to the session's writer, which the caller has locked
+fn write_frame(writer: &mut Option<Box<WriteHalf + Send>>, header: Header, data: &[u8]) -> Result<()> {
+    match *writer {
+        Some(ref mut w) => {
+            try!(w.write_all(&header.encode()));
+            try!(w.write_all(data));
+            try!(w.flush());
+            Ok(())
+        }
+        None => Err(Error::Mux("the session is closed".to_owned()))
+    }
+}
+
+/// Reads a frame header, returning false if the connection ended cleanly before it
+fn read_header<R: Read>(r: &mut R, buf: &mut [u8; HEADER_LEN]) -> Result<bool> {
+    let mut filled = 0;
+    while filled < HEADER_LEN {
+        let n = try!(r.read(&mut buf[filled..]));
+        if n == 0 {
+            if filled == 0 {
+                return Ok(false);
+            }
+            return Err(Error::Mux("the connection ended inside a frame header".to_owned()));
+        }
+        filled += n;
+    }
+    Ok(true)
+}
+
+fn read_frames<R: Read>(inner: &Inner, r: &mut R) -> Result<()> {
+    let mut buf = [0; HEADER_LEN];
+    while try!(read_header(r, &mut buf)) {
+        let header = try!(Header::decode(&buf));
+        match header.kind {
+            TYPE_DATA | TYPE_WINDOW_UPDATE => {
+                let mut data = Vec::new();
+                if header.kind == TYPE_DATA {
+                    // no window is ever bigger than this, so don't allocate more
+                    if header.length > INITIAL_WINDOW {
+                        return Err(Error::Mux(format!("{} byte data frame is too large", header.length)));
+                    }
+                    data = vec![0; header.length as usize];
+                    try!(r.read_exact(&mut data));
+                }
+                if let Some(reply) = try!(inner.handle_stream_frame(&header, data)) {
+                    try!(inner.send(reply, &[]));
+                }
+            }
+            TYPE_PING => {
+                if header.flags & FLAG_SYN != 0 {
+                    try!(inner.send(Header{ kind: TYPE_PING, flags: FLAG_ACK, stream_id: 0, length: header.length }, &[]));
+                }
+            }
+            TYPE_GO_AWAY => return Ok(()),
+            kind => return Err(Error::Mux(format!("unknown frame type {}", kind)))
+        }
+    }
+    Ok(())
+}
+
+/// One end of a multiplexed connection
+///
+/// Dropping a session closes it, then waits for its reading thread, which closing has woken up.
+pub struct Session {
+    inner: Arc<Inner>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl Session {
+    /// Starts a session over the two halves of a connection
+    ///
+    /// A thread is spawned to read from `reader`.  When the connection ends, the other side sends
+    /// `GoAway`, or it breaks the protocol, the thread closes the session and exits.  `writer` must
+    /// be able to shut down the connection `reader` reads from, like a clone of the same
+    /// `TcpStream`, or the writing half of the same `PipeEnd`.
+    pub fn new<R, W>(reader: R, writer: W, mode: Mode) -> Session
+        where R: Read + Send + 'static, W: Write + Shutdown + Send + 'static {
+        let next_id = match mode {
+            Mode::Client => 1,
+            Mode::Server => 2,
+        };
+        let writer: Box<WriteHalf + Send> = Box::new(writer);
+        let inner = Arc::new(Inner{
+            shared: Mutex::new(Shared{ streams: HashMap::new(), incoming: VecDeque::new(), next_id: next_id,
+                                       last_remote_id: 0, closed: false }),
+            changed: Condvar::new(),
+            writer: Mutex::new(Some(writer)),
+        });
+
+        let thread_inner = inner.clone();
+        let handle = thread::spawn(move || {
+            let mut reader = reader;
+            let code = match read_frames(&thread_inner, &mut reader) {
+                Err(Error::Mux(_)) => GO_AWAY_PROTOCOL_ERROR,
+                _ => GO_AWAY_NORMAL,
+            };
+            thread_inner.shut_down(code);
+        });
+        Session{ inner: inner, reader: Some(handle) }
+    }
+
+    /// Opens a new stream
+    pub fn open(&self) -> Result<Stream> {
+        // the other side requires our IDs to go up, so the SYN is sent before anyone else can
+        // take the next ID
+        let mut writer = self.inner.writer.lock().unwrap();
+        let id = {
+            let mut shared = self.inner.shared.lock().unwrap();
+            if shared.closed {
+                return Err(Error::Mux("the session is closed".to_owned()));
+            }
+            let id = shared.next_id;
+            shared.next_id += 2;
+            shared.streams.insert(id, StreamState::new());
+            id
+        };
+        let sent = write_frame(&mut writer, Header{ kind: TYPE_WINDOW_UPDATE, flags: FLAG_SYN, stream_id: id, length: 0 }, &[]);
+        // unlocked before the stream exists, as dropping it sends a frame too
+        drop(writer);
+        let stream = Stream{ id: id, inner: self.inner.clone() };
+        try!(sent);
+        Ok(stream)
+    }
+
+    /// Waits for the other side to open a stream
+    pub fn accept(&self) -> Result<Stream> {
+        let mut shared = self.inner.shared.lock().unwrap();
+        loop {
+            if let Some(id) = shared.incoming.pop_front() {
+                return Ok(Stream{ id: id, inner: self.inner.clone() });
+            }
+            if shared.closed {
+                return Err(Error::Mux("the session is closed".to_owned()));
+            }
+            shared = self.inner.changed.wait(shared).unwrap();
+        }
+    }
+
+    /// Opens a stream and negotiates the first of `protocols` the other side supports
+    pub fn open_protocol(&self, protocols: &[&str]) -> Result<(Stream, String)> {
+        let mut stream = try!(self.open());
+        let protocol = try!(multistream::select_one_of(&mut stream, protocols));
+        Ok((stream, protocol))
+    }
+
+    /// Accepts a stream and negotiates one of the protocols `listener` supports
+    pub fn accept_protocol(&self, listener: &multistream::Listener) -> Result<(Stream, String)> {
+        let mut stream = try!(self.accept());
+        let protocol = try!(listener.negotiate(&mut stream));
+        Ok((stream, protocol))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.shared.lock().unwrap().closed
+    }
+
+    /// Tells the other side we are going away, and shuts down the connection
+    ///
+    /// Any streams still open stop working.  This doesn't wait for the reading thread.
+    pub fn close(&self) {
+        self.inner.shut_down(GO_AWAY_NORMAL);
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.close();
+        if let Some(handle) = self.reader.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// One substream of a session
+///
+/// Dropping a stream closes it if the other side has finished sending, and resets it otherwise.
+pub struct Stream {
+    id: u32,
+    inner: Arc<Inner>,
+}
+
+fn to_io_error(e: Error) -> io::Error {
+    match e {
+        Error::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::ConnectionAborted, format!("{}", e))
+    }
+}
+
+impl Stream {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Finishes sending; we can still read whatever the other side sends
+    pub fn close(&mut self) -> Result<()> {
+        {
+            let mut shared = self.inner.shared.lock().unwrap();
+            match shared.streams.get_mut(&self.id) {
+                Some(st) => {
+                    if st.local_closed || st.reset {
+                        return Ok(());
+                    }
+                    st.local_closed = true;
+                }
+                None => return Ok(())
+            }
+        }
+        self.inner.send_flag(self.id, FLAG_FIN, 0)
+    }
+
+    /// Aborts the stream in both directions
+    pub fn reset(&mut self) -> Result<()> {
+        {
+            let mut shared = self.inner.shared.lock().unwrap();
+            match shared.streams.get_mut(&self.id) {
+                Some(st) => {
+                    if st.reset {
+                        return Ok(());
+                    }
+                    st.reset = true;
+                }
+                None => return Ok(())
+            }
+        }
+        self.inner.changed.notify_all();
+        self.inner.send_flag(self.id, FLAG_RST, 0)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let result;
+        {
+            let mut shared = self.inner.shared.lock().unwrap();
+            loop {
+                let attempt = {
+                    let closed = shared.closed;
+                    match shared.streams.get_mut(&self.id) {
+                        Some(st) => st.try_read(buf, closed),
+                        None => Some(Err(io::Error::new(io::ErrorKind::NotConnected, "the stream is gone")))
+                    }
+                };
+                match attempt {
+                    Some(r) => {
+                        result = r;
+                        break;
+                    }
+                    None => shared = self.inner.changed.wait(shared).unwrap()
+                }
+            }
+        }
+
+        let (n, update) = try!(result);
+        if let Some(delta) = update {
+            try!(self.inner.send_flag(self.id, 0, delta).map_err(to_io_error));
+        }
+        Ok(n)
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let result;
+        {
+            let mut shared = self.inner.shared.lock().unwrap();
+            loop {
+                let attempt = {
+                    let closed = shared.closed;
+                    match shared.streams.get_mut(&self.id) {
+                        Some(st) => st.try_reserve(buf.len(), closed),
+                        None => Some(Err(io::Error::new(io::ErrorKind::NotConnected, "the stream is gone")))
+                    }
+                };
+                match attempt {
+                    Some(r) => {
+                        result = r;
+                        break;
+                    }
+                    None => shared = self.inner.changed.wait(shared).unwrap()
+                }
+            }
+        }
+
+        let n = try!(result);
+        let header = Header{ kind: TYPE_DATA, flags: 0, stream_id: self.id, length: n as u32 };
+        try!(self.inner.send(header, &buf[..n]).map_err(to_io_error));
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        let flags = {
+            let mut shared = self.inner.shared.lock().unwrap();
+            match shared.streams.remove(&self.id) {
+                Some(ref st) if st.reset => 0,
+                Some(ref st) if !st.remote_closed => FLAG_RST,
+                Some(ref st) if !st.local_closed => FLAG_FIN,
+                _ => 0
+            }
+        };
+        if flags != 0 {
+            let _ = self.inner.send_flag(self.id, flags, 0);
+        }
+    }
+}
+
+
+#[cfg(test)]
+fn session_pair() -> (Session, Session) {
+    use pipe::pipe;
+
+    let (a, b) = pipe();
+    let (a_reader, a_writer) = a.split();
+    let (b_reader, b_writer) = b.split();
+    (Session::new(a_reader, a_writer, Mode::Client), Session::new(b_reader, b_writer, Mode::Server))
+}
+
+#[test]
+fn test_header() {
+    let header = Header{ kind: TYPE_WINDOW_UPDATE, flags: FLAG_SYN | FLAG_FIN, stream_id: 0x01020304, length: 0x0a0b0c0d };
+    let bytes = header.encode();
+    assert_eq!(&bytes[..], &[0, 1, 0, 5, 1, 2, 3, 4, 10, 11, 12, 13]);
+    assert_eq!(Header::decode(&bytes).unwrap(), header);
+
+    let mut bytes = bytes;
+    bytes[0] = 1;
+    assert!(Header::decode(&bytes).is_err());
+}
+
+#[test]
+fn test_many_concurrent_streams() {
+    let (client, server) = session_pair();
+
+    let server_thread = thread::spawn(move || {
+        let mut listener = multistream::Listener::new();
+        listener.add("/ipfs/kad/1.0.0");
+        listener.add("/echo/1.0.0");
+        let mut handlers = Vec::new();
+        // echo everything back once the client has finished sending
+        while let Ok((mut stream, protocol)) = server.accept_protocol(&listener) {
+            handlers.push(thread::spawn(move || {
+                assert_eq!(protocol, "/echo/1.0.0");
+                let mut data = Vec::new();
+                stream.read_to_end(&mut data).unwrap();
+                stream.write_all(&data).unwrap();
+                stream.close().unwrap();
+            }));
+        }
+        let n = handlers.len();
+        for h in handlers {
+            h.join().unwrap();
+        }
+        n
+    });
+
+    let client = Arc::new(client);
+    let threads: Vec<_> = (0..32).map(|i| {
+        let client = client.clone();
+        thread::spawn(move || {
+            let (mut stream, protocol) = client.open_protocol(&["/bitswap/1.0.0", "/echo/1.0.0"]).unwrap();
+            assert_eq!(protocol, "/echo/1.0.0");
+            assert_eq!(stream.id() % 2, 1);
+            // more than the window, so the writer has to wait for updates
+            let data: Vec<u8> = (0..2 * INITIAL_WINDOW as usize + 1000 * i).map(|j| (i + j) as u8).collect();
+            stream.write_all(&data).unwrap();
+            stream.close().unwrap();
+            let mut echoed = Vec::new();
+            stream.read_to_end(&mut echoed).unwrap();
+            assert!(echoed == data);
+        })
+    }).collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    drop(client);
+    assert_eq!(server_thread.join().unwrap(), 32);
+}
+
+#[test]
+fn test_flow_control() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    let (client, server) = session_pair();
+    let mut a = client.open().unwrap();
+    let mut b = server.accept().unwrap();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let writer_done = done.clone();
+    let writer = thread::spawn(move || {
+        a.write_all(&vec![7; INITIAL_WINDOW as usize + 1]).unwrap();
+        writer_done.store(true, Ordering::SeqCst);
+        a
+    });
+
+    // nothing is being read, so the last byte can't be sent
+    thread::sleep(Duration::from_millis(200));
+    assert!(!done.load(Ordering::SeqCst));
+
+    let mut buf = vec![0; INITIAL_WINDOW as usize + 1];
+    b.read_exact(&mut buf).unwrap();
+    assert!(buf.iter().all(|&x| x == 7));
+    let mut a = writer.join().unwrap();
+    assert!(done.load(Ordering::SeqCst));
+
+    a.close().unwrap();
+    assert_eq!(b.read(&mut buf).unwrap(), 0);
+    b.write_all(b"still open this way").unwrap();
+    let mut s = String::new();
+    b.close().unwrap();
+    a.read_to_string(&mut s).unwrap();
+    assert_eq!(s, "still open this way");
+}
+
+#[test]
+fn test_reset_and_session_close() {
+    let (client, server) = session_pair();
+    let mut a = client.open().unwrap();
+    a.write_all(b"hello").unwrap();
+
+    let mut b = server.accept().unwrap();
+    let mut buf = [0; 5];
+    b.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+
+    a.reset().unwrap();
+    assert_eq!(b.read(&mut buf).unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+    assert_eq!(a.write(b"more").unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+
+    // closing the session ends pending accepts on the other side
+    let waiting = thread::spawn(move || server.accept().is_err());
+    client.close();
+    assert!(waiting.join().unwrap());
+    assert!(client.is_closed());
+    assert!(client.open().is_err());
+}
+
+#[test]
+fn test_bad_syn() {
+    use pipe::pipe;
+
+    // ID 0, one of the server's own IDs, and IDs that go backwards or repeat
+    for ids in &[vec![0], vec![2], vec![3, 1], vec![1, 1]] {
+        let (mut raw, b) = pipe();
+        let (b_reader, b_writer) = b.split();
+        let server = Session::new(b_reader, b_writer, Mode::Server);
+        for &id in ids {
+            raw.write_all(&Header{ kind: TYPE_WINDOW_UPDATE, flags: FLAG_SYN, stream_id: id, length: 0 }.encode()).unwrap();
+        }
+
+        // all but the last SYN are acknowledged, then the session ends with a protocol error
+        let mut buf = [0; HEADER_LEN];
+        for &id in &ids[..ids.len() - 1] {
+            raw.read_exact(&mut buf).unwrap();
+            assert_eq!(Header::decode(&buf).unwrap(), Header{ kind: TYPE_WINDOW_UPDATE, flags: FLAG_ACK, stream_id: id, length: 0 });
+        }
+        raw.read_exact(&mut buf).unwrap();
+        assert_eq!(Header::decode(&buf).unwrap(), Header{ kind: TYPE_GO_AWAY, flags: 0, stream_id: 0, length: GO_AWAY_PROTOCOL_ERROR });
+        assert_eq!(raw.read(&mut buf).unwrap(), 0);
+
+        let mut accepted = 0;
+        while server.accept().is_ok() {
+            accepted += 1;
+        }
+        assert_eq!(accepted, ids.len() - 1);
+    }
+}
+
+#[test]
+fn test_drop_session() {
+    let (client, server) = session_pair();
+    let mut stream = server.open().unwrap();
+
+    drop(client);
+    // the server sees the connection close, and closes too
+    assert!(server.accept().is_err());
+    assert!(server.is_closed());
+    assert!(server.open().is_err());
+    assert_eq!(stream.write(b"hello").unwrap_err().kind(), io::ErrorKind::ConnectionAborted);
+}
+
+#[test]
+fn test_drop_with_silent_peer() {
+    use std::net::TcpListener;
+    use pipe::pipe;
+
+    // the peer never answers our GoAway, or closes its end
+    let (mut raw, b) = pipe();
+    let (b_reader, b_writer) = b.split();
+    drop(Session::new(b_reader, b_writer, Mode::Server));
+    let mut buf = [0; HEADER_LEN];
+    raw.read_exact(&mut buf).unwrap();
+    assert_eq!(Header::decode(&buf).unwrap(), Header{ kind: TYPE_GO_AWAY, flags: 0, stream_id: 0, length: GO_AWAY_NORMAL });
+    assert_eq!(raw.read(&mut buf).unwrap(), 0);
+
+    // the same over TCP, where dropping our clone of the socket alone wouldn't close it
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (mut raw, _) = listener.accept().unwrap();
+    let reader = client.try_clone().unwrap();
+    drop(Session::new(reader, client, Mode::Client));
+    raw.read_exact(&mut buf).unwrap();
+    assert_eq!(Header::decode(&buf).unwrap(), Header{ kind: TYPE_GO_AWAY, flags: 0, stream_id: 0, length: GO_AWAY_NORMAL });
+    assert_eq!(raw.read(&mut buf).unwrap(), 0);
+}
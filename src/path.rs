@@ -0,0 +1,168 @@
+//! Resolving `/ipfs/<hash>/a/b/c` paths through the merkledag
+
+use std::fmt;
+use std::str::FromStr;
+
+use protobuf::core::Message;
+
+use blockstore::Blockstore;
+use cid::Cid;
+use error::{Error, Result};
+use multihash::MultihashBytes;
+use unixfs;
+use ::Node;
+
+/// A parsed ipfs path: a root CID followed by zero or more link names
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IpfsPath {
+    root: Cid,
+    components: Vec<String>,
+}
+
+impl IpfsPath {
+    pub fn new(root: Cid, components: Vec<String>) -> IpfsPath {
+        IpfsPath{ root: root, components: components }
+    }
+
+    pub fn root(&self) -> &Cid {
+        &self.root
+    }
+
+    pub fn components(&self) -> &[String] {
+        &self.components
+    }
+}
+
+impl FromStr for IpfsPath {
+    type Err = Error;
+
+    /// Parses `/ipfs/<hash>/...`, or a bare `<hash>/...`
+    fn from_str(s: &str) -> Result<IpfsPath> {
+        let rest = if s.starts_with("/ipfs/") {
+            &s[6..]
+        } else if s.starts_with('/') {
+            return Err(Error::Path(format!("{:?} is not an /ipfs/ path", s)));
+        } else {
+            s
+        };
+
+        let mut parts = rest.split('/').filter(|p| !p.is_empty());
+        let root = try!(parts.next().ok_or_else(|| Error::Path(format!("{:?} has no root hash", s))));
+        let root = try!(root.parse());
+
+        Ok(IpfsPath{ root: root, components: parts.map(|p| p.to_owned()).collect() })
+    }
+}
+
+impl fmt::Display for IpfsPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "/ipfs/{}", self.root));
+        for c in &self.components {
+            try!(write!(f, "/{}", c));
+        }
+        Ok(())
+    }
+}
+
+/// The result of resolving a path
+#[derive(Debug)]
+pub struct Resolved {
+    /// The node at the end of the path, already loaded
+    pub node: Node,
+    /// The hash of every node along the way, starting with the root and ending with `node`
+    pub chain: Vec<MultihashBytes>,
+}
+
+/// Follows the named links in `path`, loading each node along the way from `store`
+///
+/// Every node except the last one must be a unixfs directory.
+pub fn resolve<B: Blockstore>(store: &B, path: &IpfsPath) -> Result<Resolved> {
+    let mut node = try!(Node::from_mh(path.root()));
+    try!(node.load_from(store));
+    let mut chain = vec![node.mh_bytes.clone()];
+    let mut so_far = format!("/ipfs/{}", path.root());
+
+    for name in path.components() {
+        let next_hash = {
+            let pbnode = node.get_node();
+
+            let mut data = unixfs::Data::new();
+            let is_dir = data.merge_from_bytes(pbnode.get_Data()).is_ok()
+                && data.get_Type() == unixfs::Data_DataType::Directory;
+            if !is_dir {
+                return Err(Error::NotADirectory(so_far));
+            }
+
+            match pbnode.get_Links().iter().find(|l| l.get_Name() == &name[..]) {
+                Some(link) => MultihashBytes(link.get_Hash().to_vec()),
+                None => return Err(Error::NoSuchLink(so_far, name.clone()))
+            }
+        };
+
+        node = try!(Node::from_mh(&next_hash));
+        try!(node.load_from(store));
+        chain.push(next_hash);
+        so_far.push('/');
+        so_far.push_str(name);
+    }
+
+    Ok(Resolved{ node: node, chain: chain })
+}
+
+
+#[test]
+fn test_parse_path() {
+    let p: IpfsPath = "/ipfs/QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe/docs//readme.md/".parse().unwrap();
+    assert_eq!(p.root().to_string(), "QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe");
+    assert_eq!(p.components(), &["docs".to_owned(), "readme.md".to_owned()]);
+    assert_eq!(p.to_string(), "/ipfs/QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe/docs/readme.md");
+
+    let bare: IpfsPath = "QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe".parse().unwrap();
+    assert_eq!(bare.components().len(), 0);
+
+    assert!("/ipns/QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe".parse::<IpfsPath>().is_err());
+    assert!("/ipfs/".parse::<IpfsPath>().is_err());
+    assert!("/ipfs/notahash/a".parse::<IpfsPath>().is_err());
+}
+
+#[test]
+fn test_resolve() {
+    use blockstore::MemBlockstore;
+    use directory::DirectoryBuilder;
+    use importer::{import_file, ImportOptions};
+
+    let mut store = MemBlockstore::new();
+    let file = import_file(&mut store, &b"hello world\n"[..], &ImportOptions::default()).unwrap();
+    let mut docs = DirectoryBuilder::new();
+    docs.add_node("readme.md", &file).unwrap();
+    let docs = docs.build(&mut store).unwrap();
+    let mut root = DirectoryBuilder::new();
+    root.add_node("docs", &docs).unwrap();
+    let root = root.build(&mut store).unwrap();
+
+    let path: IpfsPath = format!("/ipfs/{}/docs/readme.md", root.mh_str.0).parse().unwrap();
+    let resolved = resolve(&store, &path).unwrap();
+    assert_eq!(resolved.node.mh_bytes, file.mh_bytes);
+    assert_eq!(resolved.chain, vec![root.mh_bytes.clone(), docs.mh_bytes.clone(), file.mh_bytes.clone()]);
+
+    let missing: IpfsPath = format!("/ipfs/{}/docs/nope", root.mh_str.0).parse().unwrap();
+    match resolve(&store, &missing) {
+        Err(Error::NoSuchLink(ref at, ref name)) => {
+            assert_eq!(*at, format!("/ipfs/{}/docs", root.mh_str.0));
+            assert_eq!(name, "nope");
+        }
+        other => panic!("unexpected result {:?}", other)
+    }
+
+    let through_file: IpfsPath = format!("/ipfs/{}/docs/readme.md/x", root.mh_str.0).parse().unwrap();
+    match resolve(&store, &through_file) {
+        Err(Error::NotADirectory(_)) => {}
+        other => panic!("unexpected result {:?}", other)
+    }
+
+    store.delete(&docs.mh_bytes).unwrap();
+    match resolve(&store, &path) {
+        Err(Error::MissingBlock(ref h)) => assert_eq!(*h, docs.mh_str.0),
+        other => panic!("unexpected result {:?}", other)
+    }
+}
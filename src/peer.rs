@@ -0,0 +1,186 @@
+//! Peer IDs
+//!
+//! A peer's ID is the multihash of its serialized `crypto::PublicKey`, and is usually written in
+//! base58.  Anyone who is handed a public key can check it against the ID they expected.  Small
+//! keys, such as Ed25519 ones, are embedded in the ID with the identity multihash, so the key can
+//! be recovered from the ID alone.
+
+use std::fmt;
+use std::str::FromStr;
+
+use protobuf;
+
+use crypto::{PrivateKey, PublicKey};
+use dht::Message_Peer;
+use error::{Error, Result};
+use keys;
+use multihash::ParsedMultihash;
+use routing::Key;
+
+/// The identity of a peer
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PeerId {
+    hash: ParsedMultihash,
+}
+
+impl PeerId {
+    /// The ID of the peer that owns `key`
+    pub fn from_public_key(key: &PublicKey) -> Result<PeerId> {
+        PeerId::from_bytes(&try!(keys::public_key_hash(key)))
+    }
+
+    /// The ID of the peer that owns `key`
+    pub fn from_private_key(key: &PrivateKey) -> Result<PeerId> {
+        PeerId::from_public_key(&try!(keys::public_key(key)))
+    }
+
+    /// Parses raw multihash bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<PeerId> {
+        Ok(PeerId{ hash: try!(ParsedMultihash::from_bytes(bytes)) })
+    }
+
+    /// Parses the ID out of a DHT peer record
+    pub fn from_message_peer(peer: &Message_Peer) -> Result<PeerId> {
+        peer.get_id().parse()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.hash.to_bytes()
+    }
+
+    pub fn to_base58(&self) -> String {
+        self.hash.to_string()
+    }
+
+    pub fn as_multihash(&self) -> &ParsedMultihash {
+        &self.hash
+    }
+
+    /// The public key embedded in an identity multihash ID, if this is one
+    pub fn public_key(&self) -> Option<PublicKey> {
+        if self.hash.code() != keys::IDENTITY_CODE {
+            return None;
+        }
+        protobuf::parse_from_bytes(self.hash.digest()).ok()
+    }
+
+    /// This peer's point in the DHT keyspace
+    pub fn routing_key(&self) -> Result<Key> {
+        Key::from_bytes(&self.to_bytes())
+    }
+
+    /// A DHT peer record for this peer, with no addresses
+    pub fn to_message_peer(&self) -> Message_Peer {
+        let mut peer = Message_Peer::new();
+        peer.set_id(self.to_base58());
+        peer
+    }
+
+    /// Whether `key` is the key this ID was derived from
+    pub fn matches_public_key(&self, key: &PublicKey) -> Result<bool> {
+        Ok(try!(PeerId::from_public_key(key)) == *self)
+    }
+
+    /// Like `matches_public_key`, but a mismatch is an error
+    pub fn verify_public_key(&self, key: &PublicKey) -> Result<()> {
+        let actual = try!(PeerId::from_public_key(key));
+        if actual != *self {
+            return Err(Error::PeerIdMismatch(self.to_base58(), actual.to_base58()));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PeerId {
+    type Err = Error;
+
+    /// Parses a base58 peer ID
+    fn from_str(s: &str) -> Result<PeerId> {
+        Ok(PeerId{ hash: try!(s.parse()) })
+    }
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.hash)
+    }
+}
+
+impl fmt::Debug for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PeerId({})", self.hash)
+    }
+}
+
+impl<'a> From<&'a PeerId> for Message_Peer {
+    fn from(id: &'a PeerId) -> Message_Peer {
+        id.to_message_peer()
+    }
+}
+
+
+#[test]
+fn test_peer_id_from_public_key() {
+    use crypto::KeyType;
+
+    // not a real RSA key, but the ID only depends on the protobuf encoding, and this one is too
+    // long to be inlined
+    let mut key = PublicKey::new();
+    key.set_Type(KeyType::RSA);
+    key.set_Data(vec![b'x'; 50]);
+
+    let id = PeerId::from_public_key(&key).unwrap();
+    assert_eq!(id.to_base58(), "QmeyQDnvr8VEWF7PFEDN9Q9tQKqqttZsWDbswBLJoNc5vE");
+    assert_eq!(format!("{}", id), "QmeyQDnvr8VEWF7PFEDN9Q9tQKqqttZsWDbswBLJoNc5vE");
+    assert_eq!(id, "QmeyQDnvr8VEWF7PFEDN9Q9tQKqqttZsWDbswBLJoNc5vE".parse().unwrap());
+    assert!(id.matches_public_key(&key).unwrap());
+    id.verify_public_key(&key).unwrap();
+
+    key.set_Data(vec![b'y'; 50]);
+    assert!(!id.matches_public_key(&key).unwrap());
+    match id.verify_public_key(&key) {
+        Err(Error::PeerIdMismatch(ref expected, _)) => assert_eq!(expected, "QmeyQDnvr8VEWF7PFEDN9Q9tQKqqttZsWDbswBLJoNc5vE"),
+        other => panic!("unexpected result {:?}", other)
+    }
+
+    assert!("not base58!".parse::<PeerId>().is_err());
+    assert!(PeerId::from_bytes(&[0x12, 0x20, 1, 2, 3]).is_err());
+}
+
+#[test]
+fn test_peer_id_from_private_key() {
+    let privkey = keys::generate_rsa(1024);
+    let pubkey = keys::public_key(&privkey).unwrap();
+    let id = PeerId::from_private_key(&privkey).unwrap();
+    assert_eq!(id, PeerId::from_public_key(&pubkey).unwrap());
+    assert!(id.matches_public_key(&pubkey).unwrap());
+}
+
+#[test]
+fn test_inline_peer_id() {
+    let privkey = keys::ed25519_from_seed(&[7; 32]);
+    let pubkey = keys::public_key(&privkey).unwrap();
+    let id = PeerId::from_private_key(&privkey).unwrap();
+    // the prefix of every Ed25519 peer ID in base58
+    assert!(id.to_base58().starts_with("12D3KooW"));
+    assert_eq!(id.public_key(), Some(pubkey.clone()));
+    id.verify_public_key(&pubkey).unwrap();
+
+    let rsa: PeerId = "QmeyQDnvr8VEWF7PFEDN9Q9tQKqqttZsWDbswBLJoNc5vE".parse().unwrap();
+    assert_eq!(rsa.public_key(), None);
+}
+
+#[test]
+fn test_message_peer() {
+    use std::collections::HashSet;
+
+    let id: PeerId = "QmeyQDnvr8VEWF7PFEDN9Q9tQKqqttZsWDbswBLJoNc5vE".parse().unwrap();
+    let peer = Message_Peer::from(&id);
+    assert_eq!(peer.get_id(), "QmeyQDnvr8VEWF7PFEDN9Q9tQKqqttZsWDbswBLJoNc5vE");
+    assert_eq!(PeerId::from_message_peer(&peer).unwrap(), id);
+    assert_eq!(id.routing_key().unwrap(), Key::from_peer_id(peer.get_id()).unwrap());
+
+    let mut set = HashSet::new();
+    set.insert(id.clone());
+    assert!(set.contains(&PeerId::from_message_peer(&peer).unwrap()));
+}
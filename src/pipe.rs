@@ -0,0 +1,219 @@
+//! An in-memory, bidirectional byte stream
+//!
+//! `pipe()` returns two connected ends; whatever is written to one can be read from the other.
+//! Reads block until data arrives, and return end-of-file once the other end has been dropped.
+//! This stands in for a network connection when testing protocols that run over `Read + Write`.
+//! An end can be split into separate reading and writing halves, for use from two threads, and
+//! shut down from its writing half to wake up a read blocked on the other.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// One direction of a pipe
+struct Channel {
+    state: Mutex<ChannelState>,
+    /// Signalled whenever anything in `state` changes
+    changed: Condvar,
+}
+
+struct ChannelState {
+    /// Data written but not yet read
+    data: VecDeque<u8>,
+    /// The reading half has gone, so writes fail
+    reader_closed: bool,
+    /// The writing half has gone, so reads return end-of-file once the data runs out
+    writer_closed: bool,
+}
+
+impl Channel {
+    fn new() -> Arc<Channel> {
+        Arc::new(Channel{ state: Mutex::new(ChannelState{ data: VecDeque::new(), reader_closed: false, writer_closed: false }),
+                          changed: Condvar::new() })
+    }
+
+    fn close_reader(&self) {
+        self.state.lock().unwrap().reader_closed = true;
+        self.changed.notify_all();
+    }
+
+    fn close_writer(&self) {
+        self.state.lock().unwrap().writer_closed = true;
+        self.changed.notify_all();
+    }
+}
+
+/// The reading half of a pipe end
+pub struct PipeReader {
+    incoming: Arc<Channel>,
+}
+
+/// The writing half of a pipe end
+pub struct PipeWriter {
+    outgoing: Arc<Channel>,
+    /// Kept so that `shutdown` can close the reading half too
+    incoming: Arc<Channel>,
+}
+
+/// One end of a pipe
+pub struct PipeEnd {
+    reader: PipeReader,
+    writer: PipeWriter,
+}
+
+/// Creates a connected pair of pipe ends
+pub fn pipe() -> (PipeEnd, PipeEnd) {
+    let a_to_b = Channel::new();
+    let b_to_a = Channel::new();
+    (PipeEnd{ reader: PipeReader{ incoming: b_to_a.clone() }, writer: PipeWriter{ outgoing: a_to_b.clone(), incoming: b_to_a.clone() } },
+     PipeEnd{ reader: PipeReader{ incoming: a_to_b.clone() }, writer: PipeWriter{ outgoing: b_to_a, incoming: a_to_b } })
+}
+
+impl PipeEnd {
+    pub fn split(self) -> (PipeReader, PipeWriter) {
+        (self.reader, self.writer)
+    }
+}
+
+impl PipeWriter {
+    /// Closes both directions of this end, like `TcpStream::shutdown(Shutdown::Both)`
+    ///
+    /// Reads from this end, including ones already blocked, return end-of-file, and writes fail.
+    /// The other end can still read whatever was written before.
+    pub fn shutdown(&self) {
+        self.outgoing.close_writer();
+        self.incoming.close_reader();
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.incoming.state.lock().unwrap();
+        loop {
+            if state.reader_closed {
+                return Ok(0);
+            }
+            if !state.data.is_empty() {
+                let n = cmp::min(buf.len(), state.data.len());
+                for (dst, src) in buf.iter_mut().zip(state.data.drain(..n)) {
+                    *dst = src;
+                }
+                self.incoming.changed.notify_all();
+                return Ok(n);
+            }
+            // the other end is gone
+            if state.writer_closed {
+                return Ok(0);
+            }
+            state = self.incoming.changed.wait(state).unwrap();
+        }
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.outgoing.state.lock().unwrap();
+        if state.writer_closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "the pipe has been shut down"));
+        }
+        if state.reader_closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "the other end of the pipe was dropped"));
+        }
+        state.data.extend(buf);
+        self.outgoing.changed.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.incoming.close_reader();
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.outgoing.close_writer();
+    }
+}
+
+impl Read for PipeEnd {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for PipeEnd {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+
+#[test]
+fn test_pipe() {
+    use std::thread;
+
+    let (mut a, mut b) = pipe();
+    a.write_all(b"hello ").unwrap();
+    a.write_all(b"world").unwrap();
+
+    let t = thread::spawn(move || {
+        let mut s = String::new();
+        b.read_to_string(&mut s).unwrap();
+        s
+    });
+    drop(a);
+    assert_eq!(t.join().unwrap(), "hello world");
+
+    let (mut a, b) = pipe();
+    drop(b);
+    assert!(a.write_all(b"nobody is listening").is_err());
+}
+
+#[test]
+fn test_split() {
+    let (a, mut b) = pipe();
+    let (mut reader, mut writer) = a.split();
+    writer.write_all(b"ping").unwrap();
+    let mut buf = [0; 4];
+    b.read_exact(&mut buf).unwrap();
+    b.write_all(&buf).unwrap();
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+}
+
+#[test]
+fn test_shutdown() {
+    use std::thread;
+
+    let (a, mut b) = pipe();
+    let (mut reader, mut writer) = a.split();
+    let blocked = thread::spawn(move || {
+        let mut buf = [0; 4];
+        reader.read(&mut buf).unwrap()
+    });
+    writer.write_all(b"bye").unwrap();
+    writer.shutdown();
+
+    // our blocked read wakes up, while the other end still gets what we sent
+    assert_eq!(blocked.join().unwrap(), 0);
+    assert!(writer.write_all(b"more").is_err());
+    let mut s = String::new();
+    b.read_to_string(&mut s).unwrap();
+    assert_eq!(s, "bye");
+    assert!(b.write_all(b"nobody is listening").is_err());
+}
@@ -0,0 +1,260 @@
+//! Reads the contents of a unixfs file back out of the blockstore
+//!
+//! Only the blocks on the path from the root to the chunk being read are loaded, so large files
+//! can be streamed (or read at random offsets) without holding the whole thing in memory.
+
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+use protobuf::core::Message;
+
+use blockstore::Blockstore;
+use error::{Error, Result};
+use merkledag::PBNode;
+use multihash::{Multihash, MultihashBytes};
+use unixfs;
+use ::Node;
+
+/// A `Read + Seek` view of a unixfs file
+pub struct FileReader<'a, B: Blockstore + 'a> {
+    store: &'a B,
+    root: PBNode,
+    root_data: unixfs::Data,
+    size: u64,
+    pos: u64,
+    /// The chunk we're currently reading from, and its offset in the file
+    current: Option<(u64, Vec<u8>)>,
+}
+
+/// Loads a node and decodes its unixfs data
+pub fn load_unixfs<B: Blockstore, M: Multihash>(store: &B, hash: M) -> Result<(PBNode, unixfs::Data)> {
+    let mut node = try!(Node::from_mh(hash));
+    try!(node.load_from(store));
+    let pbnode = node.get_node().clone();
+
+    let mut data = unixfs::Data::new();
+    try!(data.merge_from_bytes(pbnode.get_Data()));
+    Ok((pbnode, data))
+}
+
+impl<'a, B: Blockstore> FileReader<'a, B> {
+    /// Opens the file with the given root hash
+    pub fn new<M: Multihash>(store: &'a B, hash: M) -> Result<FileReader<'a, B>> {
+        let (root, root_data) = try!(load_unixfs(store, &hash));
+        match root_data.get_Type() {
+            unixfs::Data_DataType::File | unixfs::Data_DataType::Raw => {}
+            t => return Err(Error::Unixfs(format!("{} is a {:?}, not a file", hash.base58(), t)))
+        }
+
+        let size = root_data.get_filesize();
+        Ok(FileReader{ store: store, root: root, root_data: root_data, size: size, pos: 0, current: None })
+    }
+
+    /// The total size of the file
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Finds the chunk containing `pos`, returning its offset in the file and its contents
+    fn find_chunk(&self, pos: u64) -> Result<(u64, Vec<u8>)> {
+        let mut offset = 0;
+        let mut owned: Option<(PBNode, unixfs::Data)> = None;
+
+        loop {
+            let next_hash = {
+                let (pbnode, data) = match owned {
+                    Some((ref p, ref d)) => (p, d),
+                    None => (&self.root, &self.root_data)
+                };
+
+                // a node's own data comes before the data of its children
+                let own = data.get_Data();
+                if pos < offset + own.len() as u64 {
+                    return Ok((offset, own.to_vec()));
+                }
+                offset += own.len() as u64;
+
+                let blocksizes = data.get_blocksizes();
+                let links = pbnode.get_Links();
+                if blocksizes.len() != links.len() {
+                    return Err(Error::Unixfs(format!("node has {} links but {} blocksizes", links.len(), blocksizes.len())));
+                }
+
+                let mut found = None;
+                for (link, &size) in links.iter().zip(blocksizes) {
+                    if pos < offset + size {
+                        found = Some(link.get_Hash().to_vec());
+                        break;
+                    }
+                    offset += size;
+                }
+                try!(found.ok_or_else(|| Error::Unixfs(format!("offset {} is past the end of the file", pos))))
+            };
+
+            owned = Some(try!(load_unixfs(self.store, MultihashBytes(next_hash))));
+        }
+    }
+}
+
+fn to_io_error(e: Error) -> io::Error {
+    match e {
+        Error::Io(e) => e,
+        Error::MissingBlock(_) => io::Error::new(io::ErrorKind::NotFound, e.to_string()),
+        e => io::Error::new(io::ErrorKind::Other, e.to_string())
+    }
+}
+
+impl<'a, B: Blockstore> Read for FileReader<'a, B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let covered = match self.current {
+            Some((start, ref chunk)) => self.pos >= start && self.pos < start + chunk.len() as u64,
+            None => false
+        };
+        if !covered {
+            let chunk = try!(self.find_chunk(self.pos).map_err(to_io_error));
+            self.current = Some(chunk);
+        }
+
+        let &(start, ref chunk) = self.current.as_ref().unwrap();
+        let from = (self.pos - start) as usize;
+        let n = ::std::cmp::min(buf.len(), chunk.len() - from);
+        buf[..n].copy_from_slice(&chunk[from..from + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+/// Adds a signed offset to a position, failing if the result is negative or doesn't fit in a `u64`
+fn offset_position(base: u64, n: i64) -> io::Result<u64> {
+    if n >= 0 {
+        base.checked_add(n as u64)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek offset overflowed"))
+    } else {
+        // wrapping_neg gets i64::MIN right too
+        base.checked_sub(n.wrapping_neg() as u64)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative offset"))
+    }
+}
+
+impl<'a, B: Blockstore> Seek for FileReader<'a, B> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(n) => n,
+            SeekFrom::End(n) => try!(offset_position(self.size, n)),
+            SeekFrom::Current(n) => try!(offset_position(self.pos, n)),
+        };
+        Ok(self.pos)
+    }
+}
+
+
+#[cfg(test)]
+struct CountingBlockstore {
+    inner: ::blockstore::MemBlockstore,
+    gets: ::std::cell::Cell<usize>,
+}
+
+#[cfg(test)]
+impl Blockstore for CountingBlockstore {
+    fn get_unverified<M: Multihash>(&self, hash: M) -> Result<Vec<u8>> {
+        self.gets.set(self.gets.get() + 1);
+        self.inner.get_unverified(hash)
+    }
+    fn put<M: Multihash>(&mut self, hash: M, data: &[u8]) -> Result<()> {
+        self.inner.put(hash, data)
+    }
+    fn has<M: Multihash>(&self, hash: M) -> bool {
+        self.inner.has(hash)
+    }
+    fn delete<M: Multihash>(&mut self, hash: M) -> Result<()> {
+        self.inner.delete(hash)
+    }
+    fn iter_keys<'a>(&'a self) -> Box<Iterator<Item=MultihashBytes> + 'a> {
+        self.inner.iter_keys()
+    }
+}
+
+#[test]
+fn test_read_whole_file() {
+    use blockstore::MemBlockstore;
+    use importer::{import_file, ImportOptions};
+
+    let contents: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    let mut store = MemBlockstore::new();
+    let root = import_file(&mut store, &contents[..], &ImportOptions{ chunk_size: 7, max_links: 4 }).unwrap();
+
+    let mut reader = FileReader::new(&store, &root.mh_bytes).unwrap();
+    assert_eq!(reader.size(), 1000);
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, contents);
+}
+
+#[test]
+fn test_seek_loads_only_needed_blocks() {
+    use blockstore::MemBlockstore;
+    use importer::{import_file, ImportOptions};
+
+    let contents: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    let mut store = CountingBlockstore{ inner: MemBlockstore::new(), gets: ::std::cell::Cell::new(0) };
+    let root = import_file(&mut store, &contents[..], &ImportOptions{ chunk_size: 10, max_links: 10 }).unwrap();
+
+    // 100 leaves under 10 intermediate nodes under the root
+    let mut reader = FileReader::new(&store, &root.mh_bytes).unwrap();
+    store.gets.set(0);
+
+    assert_eq!(reader.seek(SeekFrom::Start(555)).unwrap(), 555);
+    let mut buf = [0; 5];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &contents[555..560]);
+    // one intermediate node and one leaf
+    assert_eq!(store.gets.get(), 2);
+
+    assert_eq!(reader.seek(SeekFrom::End(-3)).unwrap(), 997);
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, &contents[997..]);
+
+    assert!(reader.seek(SeekFrom::Current(-2000)).is_err());
+}
+
+#[test]
+fn test_seek_overflow() {
+    use blockstore::MemBlockstore;
+    use importer::{import_file, ImportOptions};
+    use std::{i64, u64};
+
+    let mut store = MemBlockstore::new();
+    let root = import_file(&mut store, &[7u8; 100][..], &ImportOptions{ chunk_size: 10, max_links: 10 }).unwrap();
+    let mut reader = FileReader::new(&store, &root.mh_bytes).unwrap();
+
+    // seeking past the end is allowed, and reads nothing
+    assert_eq!(reader.seek(SeekFrom::Start(u64::MAX)).unwrap(), u64::MAX);
+    let mut buf = [0; 5];
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+    assert_eq!(reader.seek(SeekFrom::End(i64::MAX)).unwrap(), 100 + i64::MAX as u64);
+    assert_eq!(reader.seek(SeekFrom::Current(i64::MAX)).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(reader.seek(SeekFrom::End(i64::MIN)).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    // failed seeks leave the position alone
+    assert_eq!(reader.seek(SeekFrom::Current(0)).unwrap(), 100 + i64::MAX as u64);
+}
+
+#[test]
+fn test_reader_rejects_non_files() {
+    use blockstore::MemBlockstore;
+
+    let mut data = unixfs::Data::new();
+    data.set_Type(unixfs::Data_DataType::Directory);
+    let mut pbnode = PBNode::new();
+    pbnode.set_Data(data.write_to_bytes().unwrap());
+    let node = Node::from_pb(pbnode).unwrap();
+
+    let mut store = MemBlockstore::new();
+    ::write_node(&mut store, &node).unwrap();
+    assert!(FileReader::new(&store, &node.mh_bytes).is_err());
+}
@@ -0,0 +1,124 @@
+//! Signed DHT records
+//!
+//! A record's signature covers its key, value and author, concatenated, like go-ipfs's
+//! `RecordBlobForSig`.  The author is the peer ID of the signing key as raw multihash bytes, as in
+//! go-ipfs, so records signed by either verify with the other.
+
+use crypto::{PrivateKey, PublicKey};
+use dht::Record;
+use error::{Error, Result};
+use keys;
+use peer::PeerId;
+use ::bin_to_hex;
+
+/// The bytes that a record's signature covers
+pub fn record_blob_for_sig(record: &Record) -> Vec<u8> {
+    let mut blob = Vec::new();
+    blob.extend(record.get_key().as_bytes());
+    blob.extend(record.get_value());
+    blob.extend(record.get_author());
+    blob
+}
+
+/// Makes a record for `key` and `value`, signed by `key`
+pub fn make_signed_record(key: &str, value: Vec<u8>, privkey: &PrivateKey) -> Result<Record> {
+    let mut record = Record::new();
+    record.set_key(key.to_owned());
+    record.set_value(value);
+    try!(sign_record(&mut record, privkey));
+    Ok(record)
+}
+
+/// Sets a record's author to the owner of `privkey`, and signs it
+pub fn sign_record(record: &mut Record, privkey: &PrivateKey) -> Result<()> {
+    record.set_author(try!(PeerId::from_private_key(privkey)).to_bytes());
+    let signature = try!(keys::sign(privkey, &record_blob_for_sig(record)));
+    record.set_signature(signature);
+    Ok(())
+}
+
+/// Checks that `pubkey` belongs to the record's author, and that the signature is good
+pub fn verify_record(record: &Record, pubkey: &PublicKey) -> Result<()> {
+    if !record.has_author() || !record.has_signature() {
+        return Err(Error::BadRecord(format!("record {:?} is not signed", record.get_key())));
+    }
+    let id = try!(PeerId::from_public_key(pubkey));
+    if id.to_bytes() != record.get_author() {
+        let author = match PeerId::from_bytes(record.get_author()) {
+            Ok(author) => author.to_base58(),
+            Err(_) => bin_to_hex(&record.get_author().to_vec())
+        };
+        return Err(Error::BadRecord(format!("record {:?} is authored by {}, but the key belongs to {}",
+                                            record.get_key(), author, id)));
+    }
+    if !try!(keys::verify(pubkey, &record_blob_for_sig(record), record.get_signature())) {
+        return Err(Error::BadRecord(format!("record {:?} has a bad signature", record.get_key())));
+    }
+    Ok(())
+}
+
+
+#[test]
+fn test_signed_record() {
+    let privkey = keys::generate_rsa(1024);
+    let pubkey = keys::public_key(&privkey).unwrap();
+    let record = make_signed_record("/v/hello", b"world".to_vec(), &privkey).unwrap();
+    assert_eq!(record.get_author(), &PeerId::from_public_key(&pubkey).unwrap().to_bytes()[..]);
+    verify_record(&record, &pubkey).unwrap();
+
+    let mut tampered = record.clone();
+    tampered.set_value(b"something else".to_vec());
+    assert!(verify_record(&tampered, &pubkey).is_err());
+
+    let mut unsigned = record.clone();
+    unsigned.clear_signature();
+    assert!(verify_record(&unsigned, &pubkey).is_err());
+}
+
+#[test]
+fn test_ed25519_record() {
+    let privkey = keys::ed25519_from_seed(&[1; 32]);
+    let record = make_signed_record("/v/hello", b"world".to_vec(), &privkey).unwrap();
+    verify_record(&record, &keys::public_key(&privkey).unwrap()).unwrap();
+
+    // an RSA key can't stand in for the Ed25519 author
+    assert!(verify_record(&record, &keys::public_key(&keys::generate_rsa(1024)).unwrap()).is_err());
+}
+
+#[test]
+fn test_wrong_author() {
+    let privkey = keys::generate_rsa(1024);
+    let other = keys::public_key(&keys::generate_rsa(1024)).unwrap();
+    let record = make_signed_record("/v/hello", b"world".to_vec(), &privkey).unwrap();
+    match verify_record(&record, &other) {
+        Err(Error::BadRecord(ref s)) => assert!(s.contains("authored by")),
+        other => panic!("unexpected result {:?}", other)
+    }
+
+    // re-signing with another key under the original author doesn't help either
+    let mut forged = record.clone();
+    let author = forged.get_author().to_owned();
+    sign_record(&mut forged, &keys::generate_rsa(1024)).unwrap();
+    forged.set_author(author);
+    assert!(verify_record(&forged, &keys::public_key(&privkey).unwrap()).is_err());
+}
+
+#[test]
+fn test_go_ipfs_record() {
+    use protobuf;
+    use ::hex_to_bin;
+
+    // a record as go-ipfs encodes it: key "/v/hello", value "world", signed by the Ed25519 key
+    // with seed [7; 32], whose peer ID is 12D3KooWRawPbxPtP1eZaJpumGnyWX2DcUyd3RQnydr3eAto4Az7
+    let bytes = hex_to_bin("0a082f762f68656c6c6f1205776f726c641a26002408011220ea4a6c63e29c520abef5507b132ec5f99547\
+                            76aebebe7b92421eea691446d22c22400348ad54f1488f3c3b2bd1f64329916372a4af3f16238a2107d30c\
+                            118bab9b39607d39c6eb19a4d7d7e2fd2ca1a6bfc0cddb04d1055bdba97284bf12f3dda607").unwrap();
+    let record: Record = protobuf::parse_from_bytes(&bytes).unwrap();
+    let author = PeerId::from_bytes(record.get_author()).unwrap();
+    assert_eq!(author.to_base58(), "12D3KooWRawPbxPtP1eZaJpumGnyWX2DcUyd3RQnydr3eAto4Az7");
+    verify_record(&record, &author.public_key().unwrap()).unwrap();
+
+    // Ed25519 signatures are deterministic, so we produce exactly the same record
+    let ours = make_signed_record("/v/hello", b"world".to_vec(), &keys::ed25519_from_seed(&[7; 32])).unwrap();
+    assert_eq!(ours, record);
+}
@@ -0,0 +1,292 @@
+//! A Kademlia routing table
+//!
+//! Peers are placed in a 256-bit keyspace by hashing their peer ID with SHA-256, and the distance
+//! between two points is their XOR.  The table keeps one bucket per common-prefix-length with the
+//! local key, each holding at most `k` peers ordered from least to most recently seen.  When a
+//! bucket is full, the least recently seen peer has to be pinged before it can be replaced.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use rust_base58::FromBase58;
+use rust_multihash::HashTypes;
+
+use dht::Message_Peer;
+use error::Result;
+use ::{bin_to_hex, hash_bytes};
+
+/// The number of peers per bucket used by go-ipfs
+pub const DEFAULT_BUCKET_SIZE: usize = 20;
+
+/// A point in the DHT keyspace
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key(pub [u8; 32]);
+
+/// The XOR distance between two keys
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Distance(pub [u8; 32]);
+
+impl Key {
+    /// Hashes some bytes into the keyspace
+    pub fn from_bytes(bytes: &[u8]) -> Result<Key> {
+        let mh = try!(hash_bytes(HashTypes::SHA2256, bytes.to_vec()));
+        let mut k = [0; 32];
+        k.copy_from_slice(&mh[2..]);
+        Ok(Key(k))
+    }
+
+    /// The keyspace point for a base58 peer ID
+    pub fn from_peer_id(id: &str) -> Result<Key> {
+        Key::from_bytes(&try!(id.from_base58()))
+    }
+
+    pub fn distance(&self, other: &Key) -> Distance {
+        let mut d = [0; 32];
+        for i in 0..32 {
+            d[i] = self.0[i] ^ other.0[i];
+        }
+        Distance(d)
+    }
+
+    /// The number of leading bits these keys have in common
+    pub fn common_prefix_len(&self, other: &Key) -> usize {
+        let d = self.distance(other);
+        for (i, &byte) in d.0.iter().enumerate() {
+            if byte != 0 {
+                return i * 8 + byte.leading_zeros() as usize;
+            }
+        }
+        256
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Key({})", bin_to_hex(&self.0.to_vec()))
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    peer: Message_Peer,
+    key: Key,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Bucket {
+    /// Least recently seen first
+    entries: VecDeque<Entry>,
+    /// A peer waiting to take the place of the front entry if it fails a ping
+    replacement: Option<Entry>,
+}
+
+/// What happened when a peer was offered to the routing table
+#[derive(Clone, Debug, PartialEq)]
+pub enum Update {
+    /// The peer was new, and has been added
+    Added,
+    /// The peer was already known, and is now the most recently seen in its bucket
+    Refreshed,
+    /// The peer's bucket is full.  The given peer (the least recently seen in the bucket) should
+    /// be pinged, and the outcome reported with `ping_result`.
+    PingRequired(Message_Peer),
+    /// The peer is us, so it isn't added
+    IsSelf,
+}
+
+/// A Kademlia routing table
+#[derive(Clone, Debug)]
+pub struct RoutingTable {
+    local: Key,
+    k: usize,
+    buckets: Vec<Bucket>,
+}
+
+impl RoutingTable {
+    /// An empty table for the peer with the given keyspace point, with `k` peers per bucket
+    pub fn new(local: Key, k: usize) -> RoutingTable {
+        assert!(k > 0, "buckets must hold at least one peer");
+        RoutingTable{ local: local, k: k, buckets: (0..256).map(|_| Bucket::default()).collect() }
+    }
+
+    pub fn local_key(&self) -> &Key {
+        &self.local
+    }
+
+    fn bucket_index(&self, key: &Key) -> Option<usize> {
+        let cpl = self.local.common_prefix_len(key);
+        if cpl == 256 { None } else { Some(cpl) }
+    }
+
+    /// Records that we have heard from a peer
+    pub fn update(&mut self, peer: Message_Peer) -> Result<Update> {
+        let key = try!(Key::from_peer_id(peer.get_id()));
+        let index = match self.bucket_index(&key) {
+            Some(i) => i,
+            None => return Ok(Update::IsSelf)
+        };
+        let k = self.k;
+        let bucket = &mut self.buckets[index];
+
+        if let Some(pos) = bucket.entries.iter().position(|e| e.peer.get_id() == peer.get_id()) {
+            bucket.entries.remove(pos);
+            bucket.entries.push_back(Entry{ peer: peer, key: key });
+            return Ok(Update::Refreshed);
+        }
+
+        if bucket.entries.len() < k {
+            bucket.entries.push_back(Entry{ peer: peer, key: key });
+            return Ok(Update::Added);
+        }
+
+        bucket.replacement = Some(Entry{ peer: peer, key: key });
+        Ok(Update::PingRequired(bucket.entries[0].peer.clone()))
+    }
+
+    /// Reports the outcome of a ping requested by `update`
+    ///
+    /// If the peer answered, it stays in the table and the waiting replacement is dropped;
+    /// otherwise it is evicted and the replacement takes its place.
+    pub fn ping_result(&mut self, id: &str, alive: bool) -> Result<()> {
+        let key = try!(Key::from_peer_id(id));
+        let index = match self.bucket_index(&key) {
+            Some(i) => i,
+            None => return Ok(())
+        };
+        let bucket = &mut self.buckets[index];
+
+        let pos = match bucket.entries.iter().position(|e| e.peer.get_id() == id) {
+            Some(p) => p,
+            None => return Ok(())
+        };
+        let entry = bucket.entries.remove(pos).unwrap();
+        if alive {
+            bucket.entries.push_back(entry);
+            bucket.replacement = None;
+        } else if let Some(replacement) = bucket.replacement.take() {
+            bucket.entries.push_back(replacement);
+        }
+        Ok(())
+    }
+
+    /// Removes a peer from the table, returning true if it was there
+    pub fn remove(&mut self, id: &str) -> Result<bool> {
+        let key = try!(Key::from_peer_id(id));
+        let index = match self.bucket_index(&key) {
+            Some(i) => i,
+            None => return Ok(false)
+        };
+        let bucket = &mut self.buckets[index];
+        match bucket.entries.iter().position(|e| e.peer.get_id() == id) {
+            Some(pos) => {
+                bucket.entries.remove(pos);
+                Ok(true)
+            }
+            None => Ok(false)
+        }
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.buckets.iter().any(|b| b.entries.iter().any(|e| e.peer.get_id() == id))
+    }
+
+    /// The total number of peers in the table
+    pub fn len(&self) -> usize {
+        self.buckets.iter().fold(0, |acc, b| acc + b.entries.len())
+    }
+
+    /// The `n` peers closest to `key`, closest first
+    pub fn closest_peers(&self, key: &Key, n: usize) -> Vec<Message_Peer> {
+        let mut all: Vec<&Entry> = self.buckets.iter().flat_map(|b| b.entries.iter()).collect();
+        all.sort_by(|a, b| a.key.distance(key).cmp(&b.key.distance(key)));
+        all.into_iter().take(n).map(|e| e.peer.clone()).collect()
+    }
+}
+
+
+#[cfg(test)]
+fn test_peer(i: usize) -> Message_Peer {
+    use rust_base58::ToBase58;
+
+    let mut peer = Message_Peer::new();
+    peer.set_id(hash_bytes(HashTypes::SHA2256, format!("peer{}", i).into_bytes()).unwrap().to_base58());
+    peer
+}
+
+#[test]
+fn test_key_distance() {
+    let a = Key([0; 32]);
+    let mut b = Key([0; 32]);
+    b.0[0] = 0x10;
+    assert_eq!(a.common_prefix_len(&b), 3);
+    assert_eq!(a.common_prefix_len(&a), 256);
+    assert_eq!(a.distance(&b), b.distance(&a));
+    assert!(a.distance(&a) < a.distance(&b));
+
+    let key = Key::from_peer_id("QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe").unwrap();
+    assert_eq!(key, Key::from_peer_id("QmVtU7ths96fMgZ8YSZAbKghyieq7AjxNdcqyVzxTt3qVe").unwrap());
+    assert!(Key::from_peer_id("not base58!").is_err());
+}
+
+#[test]
+fn test_add_and_closest() {
+    let local = Key::from_peer_id(test_peer(0).get_id()).unwrap();
+    let mut table = RoutingTable::new(local, DEFAULT_BUCKET_SIZE);
+    assert_eq!(table.update(test_peer(0)).unwrap(), Update::IsSelf);
+
+    let peers: Vec<Message_Peer> = (1..30).map(test_peer).collect();
+    for p in &peers {
+        table.update(p.clone()).unwrap();
+    }
+    let added = table.len();
+    assert!(added > 0 && added <= 29);
+    assert_eq!(table.update(peers[0].clone()).unwrap(), Update::Refreshed);
+
+    let target = Key::from_bytes(b"some key").unwrap();
+    let closest = table.closest_peers(&target, 5);
+    assert_eq!(closest.len(), 5);
+
+    // the result should match a brute force sort over everything in the table
+    let mut expected: Vec<&Message_Peer> = peers.iter().filter(|p| table.contains(p.get_id())).collect();
+    expected.sort_by(|a, b| {
+        let da = Key::from_peer_id(a.get_id()).unwrap().distance(&target);
+        let db = Key::from_peer_id(b.get_id()).unwrap().distance(&target);
+        da.cmp(&db)
+    });
+    let expected_ids: Vec<&str> = expected.iter().take(5).map(|p| p.get_id()).collect();
+    let closest_ids: Vec<&str> = closest.iter().map(|p| p.get_id()).collect();
+    assert_eq!(closest_ids, expected_ids);
+
+    assert!(table.remove(peers[0].get_id()).unwrap());
+    assert!(!table.contains(peers[0].get_id()));
+    assert!(!table.remove(peers[0].get_id()).unwrap());
+}
+
+#[test]
+fn test_ping_before_evict() {
+    let local = Key::from_peer_id(test_peer(0).get_id()).unwrap();
+    let mut table = RoutingTable::new(local, 2);
+
+    // find four peers that all land in the bucket for common prefix length 0
+    let same_bucket: Vec<Message_Peer> = (1..100).map(test_peer)
+        .filter(|p| local.common_prefix_len(&Key::from_peer_id(p.get_id()).unwrap()) == 0)
+        .take(4).collect();
+    assert_eq!(same_bucket.len(), 4);
+
+    assert_eq!(table.update(same_bucket[0].clone()).unwrap(), Update::Added);
+    assert_eq!(table.update(same_bucket[1].clone()).unwrap(), Update::Added);
+
+    // the bucket is full, so the least recently seen peer needs a ping
+    assert_eq!(table.update(same_bucket[2].clone()).unwrap(), Update::PingRequired(same_bucket[0].clone()));
+    // it answered, so it stays and the newcomer is dropped
+    table.ping_result(same_bucket[0].get_id(), true).unwrap();
+    assert!(table.contains(same_bucket[0].get_id()));
+    assert!(!table.contains(same_bucket[2].get_id()));
+
+    // now peer 1 is the least recently seen
+    assert_eq!(table.update(same_bucket[3].clone()).unwrap(), Update::PingRequired(same_bucket[1].clone()));
+    table.ping_result(same_bucket[1].get_id(), false).unwrap();
+    assert!(!table.contains(same_bucket[1].get_id()));
+    assert!(table.contains(same_bucket[3].get_id()));
+    assert_eq!(table.len(), 2);
+}
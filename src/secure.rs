@@ -0,0 +1,387 @@
+//! An encrypted, mutually authenticated channel between two peers
+//!
+//! This is our own small protocol, not secio, negotiated as `PROTOCOL_ID`.  All handshake
+//! messages are varint length-prefixed frames.
+//!
+//! 1. Each side sends a proposal: three frames holding its serialized `crypto::PublicKey`, a fresh
+//!    X25519 public key, and a random 16 byte nonce.
+//! 2. Each side checks the other's identity against the peer ID it expected, then sends a
+//!    signature, made with its identity key, over both proposals (its own first).
+//! 3. The X25519 shared secret goes through HKDF-SHA256, salted with both nonces, to produce one
+//!    key for each direction.
+//! 4. Each side sends the other's nonce as its first encrypted frame, which proves that both
+//!    ended up with the same keys.
+//!
+//! Afterwards every frame is ChaCha20-Poly1305 ciphertext followed by its 16 byte tag, with a
+//! per-direction counter as the nonce.
+
+use std::cmp;
+use std::io;
+use std::io::{Read, Write};
+
+use protobuf;
+use protobuf::core::Message;
+use rand::{OsRng, Rng};
+use rust_crypto::aead::{AeadDecryptor, AeadEncryptor};
+use rust_crypto::chacha20poly1305::ChaCha20Poly1305;
+use rust_crypto::curve25519::{curve25519, curve25519_base};
+use rust_crypto::hkdf::{hkdf_expand, hkdf_extract};
+use rust_crypto::sha2::Sha256;
+use rust_crypto::util::fixed_time_eq;
+
+use codec::{read_frame, write_frame};
+use crypto::{PrivateKey, PublicKey};
+use error::{Error, Result};
+use keys;
+use peer::PeerId;
+
+/// The protocol ID to negotiate with multistream-select
+pub const PROTOCOL_ID: &'static str = "/ipfsrs/secure/1.0.0";
+
+/// The most plaintext sent in one frame
+pub const MAX_PLAINTEXT: usize = 64 * 1024;
+
+const TAG_LEN: usize = 16;
+const NONCE_LEN: usize = 16;
+const MAX_FRAME: usize = MAX_PLAINTEXT + TAG_LEN;
+const MAX_HANDSHAKE_FRAME: usize = 16 * 1024;
+const SIGNATURE_CONTEXT: &'static [u8] = b"ipfsrs secure channel handshake";
+const KEY_INFO: &'static [u8] = b"ipfsrs secure channel keys";
+
+fn read_handshake_frame<R: Read>(r: &mut R, what: &str) -> Result<Vec<u8>> {
+    match try!(read_frame(r, MAX_HANDSHAKE_FRAME)) {
+        Some(frame) => Ok(frame),
+        None => Err(Error::SecureChannel(format!("the stream ended before the {}", what)))
+    }
+}
+
+fn to_io_error(e: Error) -> io::Error {
+    match e {
+        Error::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, format!("{}", e))
+    }
+}
+
+/// What a proposal frame holds
+struct Proposal {
+    public_key: PublicKey,
+    ephemeral: Vec<u8>,
+    nonce: Vec<u8>,
+}
+
+impl Proposal {
+    fn parse(mut bytes: &[u8]) -> Result<Proposal> {
+        let key_bytes = try!(read_handshake_frame(&mut bytes, "public key"));
+        let ephemeral = try!(read_handshake_frame(&mut bytes, "ephemeral key"));
+        let nonce = try!(read_handshake_frame(&mut bytes, "nonce"));
+        if ephemeral.len() != 32 || nonce.len() != NONCE_LEN {
+            return Err(Error::SecureChannel("malformed proposal".to_owned()));
+        }
+        Ok(Proposal{ public_key: try!(protobuf::parse_from_bytes(&key_bytes)), ephemeral: ephemeral, nonce: nonce })
+    }
+}
+
+/// One direction of the encrypted stream
+struct Direction {
+    key: Vec<u8>,
+    counter: u64,
+}
+
+impl Direction {
+    fn cipher(&mut self) -> Result<ChaCha20Poly1305> {
+        if self.counter == ::std::u64::MAX {
+            return Err(Error::SecureChannel("the nonce counter ran out".to_owned()));
+        }
+        let mut nonce = [0; 8];
+        for i in 0..8 {
+            nonce[i] = (self.counter >> (56 - 8 * i)) as u8;
+        }
+        self.counter += 1;
+        Ok(ChaCha20Poly1305::new(&self.key, &nonce, &[]))
+    }
+
+    fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut frame = vec![0; plaintext.len() + TAG_LEN];
+        {
+            let (ciphertext, tag) = frame.split_at_mut(plaintext.len());
+            try!(self.cipher()).encrypt(plaintext, ciphertext, tag);
+        }
+        Ok(frame)
+    }
+
+    fn open(&mut self, frame: &[u8]) -> Result<Vec<u8>> {
+        if frame.len() < TAG_LEN {
+            return Err(Error::SecureChannel("frame is too short".to_owned()));
+        }
+        let (ciphertext, tag) = frame.split_at(frame.len() - TAG_LEN);
+        let mut plaintext = vec![0; ciphertext.len()];
+        if !try!(self.cipher()).decrypt(ciphertext, &mut plaintext, tag) {
+            return Err(Error::SecureChannel("frame failed authentication".to_owned()));
+        }
+        Ok(plaintext)
+    }
+}
+
+/// A stream that encrypts everything written to it, and decrypts everything read
+pub struct SecureStream<S> {
+    inner: S,
+    remote_peer: PeerId,
+    remote_key: PublicKey,
+    send: Direction,
+    recv: Direction,
+    /// Decrypted data not yet read
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+/// Runs the handshake as either side, using `local_key` as our identity
+///
+/// If `expected` is given, the handshake fails unless the other side proves it is that peer.
+pub fn handshake<S: Read + Write>(mut stream: S, local_key: &PrivateKey, expected: Option<&PeerId>) -> Result<SecureStream<S>> {
+    let mut rng = try!(OsRng::new());
+    let mut secret = [0; 32];
+    rng.fill_bytes(&mut secret);
+    let mut nonce = [0; NONCE_LEN];
+    rng.fill_bytes(&mut nonce);
+    let ephemeral = curve25519_base(&secret);
+
+    // 1. proposals
+    let mut local_proposal = Vec::new();
+    try!(write_frame(&mut local_proposal, &try!(try!(keys::public_key(local_key)).write_to_bytes()), MAX_HANDSHAKE_FRAME));
+    try!(write_frame(&mut local_proposal, &ephemeral, MAX_HANDSHAKE_FRAME));
+    try!(write_frame(&mut local_proposal, &nonce, MAX_HANDSHAKE_FRAME));
+    try!(write_frame(&mut stream, &local_proposal, MAX_HANDSHAKE_FRAME));
+
+    let remote_proposal = try!(read_handshake_frame(&mut stream, "proposal"));
+    let remote = try!(Proposal::parse(&remote_proposal));
+    let remote_peer = try!(PeerId::from_public_key(&remote.public_key));
+    if let Some(expected) = expected {
+        if *expected != remote_peer {
+            return Err(Error::PeerIdMismatch(expected.to_base58(), remote_peer.to_base58()));
+        }
+    }
+    if remote.ephemeral == &ephemeral[..] || remote.nonce == &nonce[..] {
+        return Err(Error::SecureChannel("the other side echoed our proposal".to_owned()));
+    }
+
+    // 2. signatures
+    let mut signed = SIGNATURE_CONTEXT.to_vec();
+    signed.extend(&local_proposal);
+    signed.extend(&remote_proposal);
+    try!(write_frame(&mut stream, &try!(keys::sign(local_key, &signed)), MAX_HANDSHAKE_FRAME));
+
+    let remote_signature = try!(read_handshake_frame(&mut stream, "signature"));
+    let mut remote_signed = SIGNATURE_CONTEXT.to_vec();
+    remote_signed.extend(&remote_proposal);
+    remote_signed.extend(&local_proposal);
+    if !try!(keys::verify(&remote.public_key, &remote_signed, &remote_signature)) {
+        return Err(Error::SecureChannel(format!("{} sent a bad signature", remote_peer)));
+    }
+
+    // 3. session keys
+    let shared = curve25519(&secret, &remote.ephemeral);
+    if shared.iter().all(|&b| b == 0) {
+        return Err(Error::SecureChannel("the other side sent a weak ephemeral key".to_owned()));
+    }
+    let mut salt = Vec::new();
+    if nonce[..] < remote.nonce[..] {
+        salt.extend(&nonce);
+        salt.extend(&remote.nonce);
+    } else {
+        salt.extend(&remote.nonce);
+        salt.extend(&nonce);
+    }
+    let mut prk = [0; 32];
+    hkdf_extract(Sha256::new(), &salt, &shared, &mut prk);
+    let mut okm = [0; 64];
+    hkdf_expand(Sha256::new(), &prk, KEY_INFO, &mut okm);
+    // the side with the smaller ephemeral key sends with the first key
+    let (send_key, recv_key) = if ephemeral[..] < remote.ephemeral[..] {
+        (okm[..32].to_vec(), okm[32..].to_vec())
+    } else {
+        (okm[32..].to_vec(), okm[..32].to_vec())
+    };
+
+    let mut secure = SecureStream{
+        inner: stream,
+        remote_peer: remote_peer,
+        remote_key: remote.public_key,
+        send: Direction{ key: send_key, counter: 0 },
+        recv: Direction{ key: recv_key, counter: 0 },
+        buf: Vec::new(),
+        pos: 0,
+    };
+
+    // 4. key confirmation
+    let frame = try!(secure.send.seal(&remote.nonce));
+    try!(write_frame(&mut secure.inner, &frame, MAX_FRAME));
+    let echoed = match try!(secure.read_plaintext()) {
+        Some(echoed) => echoed,
+        None => return Err(Error::SecureChannel("the stream ended before key confirmation".to_owned()))
+    };
+    if !fixed_time_eq(&echoed, &nonce) {
+        return Err(Error::SecureChannel("key confirmation failed".to_owned()));
+    }
+
+    Ok(secure)
+}
+
+impl<S: Read + Write> SecureStream<S> {
+    /// The authenticated identity of the other side
+    pub fn remote_peer(&self) -> &PeerId {
+        &self.remote_peer
+    }
+
+    pub fn remote_public_key(&self) -> &PublicKey {
+        &self.remote_key
+    }
+
+    /// The underlying stream; writing to it directly will corrupt the channel
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// The underlying stream; writing to it directly will corrupt the channel
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Reads and decrypts the next frame, returning `None` at the end of the stream
+    fn read_plaintext(&mut self) -> Result<Option<Vec<u8>>> {
+        match try!(read_frame(&mut self.inner, MAX_FRAME)) {
+            Some(frame) => Ok(Some(try!(self.recv.open(&frame)))),
+            None => Ok(None)
+        }
+    }
+}
+
+impl<S: Read + Write> Read for SecureStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // skip over empty frames
+        while self.pos == self.buf.len() {
+            match try!(self.read_plaintext().map_err(to_io_error)) {
+                Some(data) => {
+                    self.buf = data;
+                    self.pos = 0;
+                }
+                None => return Ok(0)
+            }
+        }
+        let n = cmp::min(buf.len(), self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<S: Read + Write> Write for SecureStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let n = cmp::min(buf.len(), MAX_PLAINTEXT);
+        let frame = try!(self.send.seal(&buf[..n]).map_err(to_io_error));
+        try!(write_frame(&mut self.inner, &frame, MAX_FRAME).map_err(to_io_error));
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+
+#[test]
+fn test_handshake_over_loopback() {
+    use std::thread;
+    use std::time::Duration;
+    use multistream;
+    use transport::{dial, Listener};
+
+    let server_key = keys::generate_rsa(1024);
+    let server_pub = keys::public_key(&server_key).unwrap();
+    let server_id = PeerId::from_public_key(&server_pub).unwrap();
+    let client_key = keys::ed25519_from_seed(&[5; 32]);
+    let client_id = PeerId::from_private_key(&client_key).unwrap();
+
+    let listener = Listener::bind(&"/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().clone();
+    let t = thread::spawn(move || {
+        let mut protocols = multistream::Listener::new();
+        protocols.add(PROTOCOL_ID);
+        let conn = listener.accept(&protocols, Duration::from_secs(5)).unwrap();
+        let mut secure = handshake(conn, &server_key, None).unwrap();
+        let client = secure.remote_peer().clone();
+        let mut buf = [0; 5];
+        secure.read_exact(&mut buf).unwrap();
+        secure.write_all(b"world").unwrap();
+        (client, buf)
+    });
+
+    let conn = dial(&addr, &[PROTOCOL_ID], Duration::from_secs(5)).unwrap();
+    let mut secure = handshake(conn, &client_key, Some(&server_id)).unwrap();
+    assert_eq!(secure.remote_peer(), &server_id);
+    assert_eq!(secure.remote_public_key(), &server_pub);
+    secure.write_all(b"hello").unwrap();
+    let mut buf = [0; 5];
+    secure.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"world");
+
+    let (client, buf) = t.join().unwrap();
+    assert_eq!(client, client_id);
+    assert_eq!(&buf, b"hello");
+}
+
+#[test]
+fn test_large_writes() {
+    use std::thread;
+    use pipe::pipe;
+
+    let (a, b) = pipe();
+    let t = thread::spawn(move || {
+        let mut secure = handshake(b, &keys::ed25519_from_seed(&[6; 32]), None).unwrap();
+        let mut data = Vec::new();
+        secure.read_to_end(&mut data).unwrap();
+        data
+    });
+    let mut secure = handshake(a, &keys::ed25519_from_seed(&[7; 32]), None).unwrap();
+    let data: Vec<u8> = (0..3 * MAX_PLAINTEXT + 10).map(|i| i as u8).collect();
+    secure.write_all(&data).unwrap();
+    drop(secure);
+    assert_eq!(t.join().unwrap(), data);
+}
+
+#[test]
+fn test_wrong_peer() {
+    use std::thread;
+    use pipe::pipe;
+
+    let (a, b) = pipe();
+    let t = thread::spawn(move || handshake(b, &keys::ed25519_from_seed(&[8; 32]), None).map(|_| ()));
+    let impostor = PeerId::from_private_key(&keys::ed25519_from_seed(&[9; 32])).unwrap();
+    match handshake(a, &keys::ed25519_from_seed(&[10; 32]), Some(&impostor)) {
+        Err(Error::PeerIdMismatch(ref expected, _)) => assert_eq!(expected, &impostor.to_base58()),
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("the handshake should have failed")
+    }
+    // we hung up, so the other side can't finish either
+    assert!(t.join().unwrap().is_err());
+}
+
+#[test]
+fn test_tampered_frame() {
+    use std::thread;
+    use pipe::pipe;
+
+    let (a, b) = pipe();
+    let t = thread::spawn(move || {
+        let mut secure = handshake(b, &keys::ed25519_from_seed(&[11; 32]), None).unwrap();
+        let mut buf = [0; 5];
+        secure.read_exact(&mut buf).map(|_| buf)
+    });
+    let mut secure = handshake(a, &keys::ed25519_from_seed(&[12; 32]), None).unwrap();
+    // a frame that wasn't encrypted with the session key
+    write_frame(secure.get_mut(), &[0; 5 + TAG_LEN], MAX_FRAME).unwrap();
+    let err = t.join().unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,192 @@
+//! An in-process network of simulated DHT nodes
+//!
+//! Every node has its own `DhtState`, and requests are delivered by calling
+//! `handler::handle_message` directly.  Nodes can be given a latency, taken offline, or removed
+//! entirely, so lookups can be tested against churn and unresponsive peers.  Everything is
+//! deterministic: peer IDs are derived from a seed and an index.
+//!
+//! Nothing actually waits.  Instead the network keeps a simulated clock, which each request
+//! advances by the time it would have taken, so tests can see how long a lookup would run.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rust_base58::ToBase58;
+use rust_multihash::HashTypes;
+
+use dht::{Message, Message_Peer};
+use error::{Error, Result};
+use handler::{DhtState, handle_message};
+use lookup::Network;
+use routing::{Key, RoutingTable};
+use ::hash_bytes;
+
+/// One simulated node
+pub struct SimNode {
+    pub peer: Message_Peer,
+    pub state: DhtState,
+    /// How long this node takes to answer
+    pub latency: Duration,
+    /// Offline nodes never answer
+    pub online: bool,
+}
+
+/// A collection of simulated nodes that can send requests to each other
+pub struct SimNetwork {
+    nodes: HashMap<String, SimNode>,
+    /// Peer IDs in the order they were added
+    order: Vec<String>,
+    k: usize,
+    /// Simulated time spent waiting for responses
+    elapsed: Duration,
+}
+
+/// A deterministic peer for index `i` of the network seeded with `seed`
+pub fn sim_peer(seed: u64, i: usize) -> Message_Peer {
+    let mut peer = Message_Peer::new();
+    let mh = hash_bytes(HashTypes::SHA2256, format!("simnet-{}-{}", seed, i).into_bytes()).unwrap();
+    peer.set_id(mh.to_base58());
+    peer
+}
+
+impl SimNetwork {
+    /// An empty network whose nodes keep `k` peers per bucket
+    pub fn new(k: usize) -> SimNetwork {
+        SimNetwork{ nodes: HashMap::new(), order: Vec::new(), k: k, elapsed: Duration::from_secs(0) }
+    }
+
+    /// A network of `n` nodes where every node has offered every other node to its routing table
+    pub fn fully_connected(n: usize, k: usize, seed: u64) -> SimNetwork {
+        let mut net = SimNetwork::new(k);
+        for i in 0..n {
+            net.add_node(sim_peer(seed, i));
+        }
+        let peers: Vec<Message_Peer> = net.order.iter().map(|id| net.nodes[id].peer.clone()).collect();
+        for node in net.nodes.values_mut() {
+            for p in &peers {
+                // full buckets just keep the peers they already have
+                let _ = node.state.routing.update(p.clone());
+            }
+        }
+        net
+    }
+
+    /// Adds a node with an empty routing table
+    pub fn add_node(&mut self, peer: Message_Peer) {
+        let local = Key::from_peer_id(peer.get_id()).unwrap();
+        let state = DhtState::new(RoutingTable::new(local, self.k), self.k);
+        self.order.push(peer.get_id().to_owned());
+        self.nodes.insert(peer.get_id().to_owned(), SimNode{ peer: peer, state: state, latency: Duration::from_millis(10), online: true });
+    }
+
+    /// Removes a node completely; other nodes may still have it in their routing tables
+    pub fn remove_node(&mut self, id: &str) -> Option<SimNode> {
+        self.order.retain(|i| i != id);
+        self.nodes.remove(id)
+    }
+
+    pub fn node(&self, id: &str) -> Option<&SimNode> {
+        self.nodes.get(id)
+    }
+
+    pub fn node_mut(&mut self, id: &str) -> Option<&mut SimNode> {
+        self.nodes.get_mut(id)
+    }
+
+    /// The IDs of every node, in the order they were added
+    pub fn ids(&self) -> &[String] {
+        &self.order
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// How long all the requests sent so far would have taken
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The `n` online nodes closest to `key`, by brute force
+    pub fn true_closest(&self, key: &Key, n: usize) -> Vec<Message_Peer> {
+        let mut online: Vec<&SimNode> = self.nodes.values().filter(|node| node.online).collect();
+        online.sort_by(|a, b| {
+            let da = a.state.routing.local_key().distance(key);
+            let db = b.state.routing.local_key().distance(key);
+            da.cmp(&db)
+        });
+        online.into_iter().take(n).map(|node| node.peer.clone()).collect()
+    }
+}
+
+impl SimNetwork {
+    /// Delivers one request, returning the response and how long it took to arrive
+    fn deliver(&mut self, from: &Message_Peer, to: &Message_Peer, msg: &Message, timeout: Duration) -> (Result<Message>, Duration) {
+        let node = match self.nodes.get_mut(to.get_id()) {
+            Some(node) => node,
+            None => return (Err(Error::Timeout(to.get_id().to_owned())), timeout)
+        };
+        if !node.online || node.latency > timeout {
+            return (Err(Error::Timeout(to.get_id().to_owned())), timeout);
+        }
+
+        // receiving a request from a peer tells us it's alive
+        let _ = node.state.routing.update(from.clone());
+        let resp = match handle_message(&mut node.state, from, msg) {
+            Ok(Some(resp)) => Ok(resp),
+            Ok(None) => Err(Error::Dht("no response".to_owned())),
+            Err(e) => Err(e)
+        };
+        (resp, node.latency)
+    }
+}
+
+impl Network for SimNetwork {
+    fn send(&mut self, from: &Message_Peer, to: &Message_Peer, msg: &Message, timeout: Duration) -> Result<Message> {
+        let (resp, took) = self.deliver(from, to, msg, timeout);
+        self.elapsed += took;
+        resp
+    }
+
+    fn send_all(&mut self, from: &Message_Peer, to: &[Message_Peer], msg: &Message, timeout: Duration) -> Vec<Result<Message>> {
+        // the requests go out together, so the batch takes as long as the slowest one
+        let mut slowest = Duration::from_secs(0);
+        let mut responses = Vec::new();
+        for peer in to {
+            let (resp, took) = self.deliver(from, peer, msg, timeout);
+            slowest = cmp::max(slowest, took);
+            responses.push(resp);
+        }
+        self.elapsed += slowest;
+        responses
+    }
+}
+
+
+#[test]
+fn test_simnet_ping() {
+    use dht::Message_MessageType;
+
+    let mut net = SimNetwork::fully_connected(5, 20, 1);
+    assert_eq!(net.len(), 5);
+    let a = net.node(&net.ids()[0]).unwrap().peer.clone();
+    let b = net.node(&net.ids()[1]).unwrap().peer.clone();
+    assert_eq!(net.node(b.get_id()).unwrap().state.routing.len(), 4);
+
+    let mut ping = Message::new();
+    ping.set_field_type(Message_MessageType::PING);
+    assert_eq!(net.send(&a, &b, &ping, Duration::from_secs(1)).unwrap(), ping);
+
+    net.node_mut(b.get_id()).unwrap().latency = Duration::from_secs(5);
+    match net.send(&a, &b, &ping, Duration::from_secs(1)) {
+        Err(Error::Timeout(ref id)) => assert_eq!(id, b.get_id()),
+        other => panic!("unexpected result {:?}", other)
+    }
+
+    net.remove_node(b.get_id());
+    assert!(net.send(&a, &b, &ping, Duration::from_secs(1)).is_err());
+
+    // 10ms for the first ping, then a second for each timeout
+    assert_eq!(net.elapsed(), Duration::from_millis(2010));
+}
@@ -0,0 +1,235 @@
+//! TCP connections to peers
+//!
+//! Peers are dialed by `Multiaddr`, such as `/ip4/1.2.3.4/tcp/4001/ipfs/Qm...`, and every new
+//! connection starts with multistream-select to agree on the protocol it will carry.  When the
+//! address ends with a peer ID, the connection remembers it; connections we accept don't know
+//! who is on the other end until something like the secure channel tells them.
+
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use error::{Error, Result};
+use multiaddr::{Multiaddr, Protocol};
+use multistream;
+use peer::PeerId;
+
+/// The multiaddr of a TCP socket address
+pub fn tcp_multiaddr(addr: &SocketAddr) -> Multiaddr {
+    let ip = match *addr {
+        SocketAddr::V4(ref a) => Protocol::Ip4(*a.ip()),
+        SocketAddr::V6(ref a) => Protocol::Ip6(*a.ip()),
+    };
+    Multiaddr::new(vec![ip, Protocol::Tcp(addr.port())])
+}
+
+/// The socket addresses a TCP multiaddr refers to, looking up DNS names if needed
+///
+/// A trailing `/ipfs/` component is ignored.
+pub fn socket_addrs(addr: &Multiaddr) -> Result<Vec<SocketAddr>> {
+    let unsupported = || Error::Transport(format!("{} isn't a TCP address", addr));
+    let without_peer = addr.without_peer_id();
+    let protocols = without_peer.protocols();
+    if protocols.len() != 2 {
+        return Err(unsupported());
+    }
+    let port = match protocols[1] {
+        Protocol::Tcp(port) => port,
+        _ => return Err(unsupported())
+    };
+    let addrs: Vec<SocketAddr> = match protocols[0] {
+        Protocol::Ip4(ip) => vec![SocketAddr::new(ip.into(), port)],
+        Protocol::Ip6(ip) => vec![SocketAddr::new(ip.into(), port)],
+        Protocol::Dns(ref host) => try!((&host[..], port).to_socket_addrs()).collect(),
+        Protocol::Dns4(ref host) => try!((&host[..], port).to_socket_addrs()).filter(|a| a.is_ipv4()).collect(),
+        Protocol::Dns6(ref host) => try!((&host[..], port).to_socket_addrs()).filter(|a| a.is_ipv6()).collect(),
+        _ => return Err(unsupported())
+    };
+    if addrs.is_empty() {
+        return Err(Error::Transport(format!("{} didn't resolve to any addresses", addr)));
+    }
+    Ok(addrs)
+}
+
+/// An open, negotiated connection
+pub struct Connection {
+    stream: TcpStream,
+    local_addr: Multiaddr,
+    remote_addr: Multiaddr,
+    remote_peer: Option<PeerId>,
+    protocol: String,
+    last_used: Instant,
+}
+
+impl Connection {
+    fn new(stream: TcpStream, remote_peer: Option<PeerId>, protocol: String) -> Result<Connection> {
+        Ok(Connection{
+            local_addr: tcp_multiaddr(&try!(stream.local_addr())),
+            remote_addr: tcp_multiaddr(&try!(stream.peer_addr())),
+            stream: stream,
+            remote_peer: remote_peer,
+            protocol: protocol,
+            last_used: Instant::now(),
+        })
+    }
+
+    pub fn local_addr(&self) -> &Multiaddr {
+        &self.local_addr
+    }
+
+    pub fn remote_addr(&self) -> &Multiaddr {
+        &self.remote_addr
+    }
+
+    /// The peer on the other end, if we know it
+    pub fn remote_peer(&self) -> Option<&PeerId> {
+        self.remote_peer.as_ref()
+    }
+
+    /// Records who is on the other end, once it has been established
+    pub fn set_remote_peer(&mut self, peer: PeerId) {
+        self.remote_peer = Some(peer);
+    }
+
+    /// The protocol agreed with multistream-select
+    pub fn protocol(&self) -> &str {
+        &self.protocol
+    }
+
+    /// When the connection was last read from or written to
+    pub fn last_used(&self) -> Instant {
+        self.last_used
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(try!(self.stream.set_read_timeout(timeout)))
+    }
+
+    /// Closes both directions of the connection
+    pub fn close(&self) -> Result<()> {
+        match self.stream.shutdown(Shutdown::Both) {
+            Ok(()) => Ok(()),
+            // already closed by the other side
+            Err(ref e) if e.kind() == io::ErrorKind::NotConnected => Ok(()),
+            Err(e) => Err(Error::Io(e))
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.last_used = Instant::now();
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.last_used = Instant::now();
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Runs multistream-select on a fresh stream, giving up if the other side is silent for `timeout`
+fn negotiate<F>(stream: &mut TcpStream, timeout: Duration, f: F) -> Result<String>
+    where F: FnOnce(&mut TcpStream) -> Result<String> {
+    try!(stream.set_read_timeout(Some(timeout)));
+    let protocol = try!(f(stream));
+    try!(stream.set_read_timeout(None));
+    Ok(protocol)
+}
+
+/// Dials `addr` and proposes `protocols` in order
+///
+/// Every resolved address is tried in turn, each for at most `timeout`.
+pub fn dial(addr: &Multiaddr, protocols: &[&str], timeout: Duration) -> Result<Connection> {
+    let mut last_err = None;
+    for socket_addr in try!(socket_addrs(addr)) {
+        match TcpStream::connect_timeout(&socket_addr, timeout) {
+            Ok(mut stream) => {
+                try!(stream.set_nodelay(true));
+                let protocol = try!(negotiate(&mut stream, timeout, |s| multistream::select_one_of(s, protocols)));
+                return Connection::new(stream, addr.peer_id().cloned(), protocol);
+            }
+            Err(e) => last_err = Some(e)
+        }
+    }
+    Err(Error::Io(last_err.unwrap()))
+}
+
+/// A socket accepting connections from peers
+pub struct Listener {
+    listener: TcpListener,
+    addr: Multiaddr,
+}
+
+impl Listener {
+    /// Listens on a TCP multiaddr; use port 0 to have one picked
+    pub fn bind(addr: &Multiaddr) -> Result<Listener> {
+        let socket_addrs = try!(socket_addrs(addr));
+        let listener = try!(TcpListener::bind(&socket_addrs[..]));
+        let local = try!(listener.local_addr());
+        Ok(Listener{ listener: listener, addr: tcp_multiaddr(&local) })
+    }
+
+    /// The address we're actually listening on
+    pub fn local_addr(&self) -> &Multiaddr {
+        &self.addr
+    }
+
+    /// Waits for the next connection, and negotiates one of the `protocols` on it
+    pub fn accept(&self, protocols: &multistream::Listener, timeout: Duration) -> Result<Connection> {
+        let (mut stream, _) = try!(self.listener.accept());
+        try!(stream.set_nodelay(true));
+        let protocol = try!(negotiate(&mut stream, timeout, |s| protocols.negotiate(s)));
+        Connection::new(stream, None, protocol)
+    }
+}
+
+
+#[test]
+fn test_socket_addrs() {
+    let addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001/ipfs/QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ".parse().unwrap();
+    assert_eq!(socket_addrs(&addr).unwrap(), vec!["127.0.0.1:4001".parse().unwrap()]);
+    let addr: Multiaddr = "/ip6/::1/tcp/4001".parse().unwrap();
+    assert_eq!(socket_addrs(&addr).unwrap(), vec!["[::1]:4001".parse().unwrap()]);
+    assert_eq!(tcp_multiaddr(&"[::1]:4001".parse().unwrap()), addr);
+
+    for s in &["/ip4/127.0.0.1/udp/4001", "/ip4/127.0.0.1", "/tcp/4001"] {
+        assert!(socket_addrs(&s.parse().unwrap()).is_err());
+    }
+}
+
+#[test]
+fn test_dial_and_accept() {
+    use std::thread;
+
+    let listener = Listener::bind(&"/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().clone();
+    assert!(socket_addrs(&addr).unwrap()[0].port() != 0);
+
+    let t = thread::spawn(move || {
+        let mut protocols = multistream::Listener::new();
+        protocols.add("/ipfs/kad/1.0.0");
+        let mut conn = listener.accept(&protocols, Duration::from_secs(5)).unwrap();
+        assert_eq!(conn.protocol(), "/ipfs/kad/1.0.0");
+        assert!(conn.remote_peer().is_none());
+        let mut buf = [0; 4];
+        conn.read_exact(&mut buf).unwrap();
+        conn.write_all(&buf).unwrap();
+    });
+
+    let mut conn = dial(&addr, &["/ipfs/id/1.0.0", "/ipfs/kad/1.0.0"], Duration::from_secs(5)).unwrap();
+    assert_eq!(conn.protocol(), "/ipfs/kad/1.0.0");
+    assert_eq!(conn.remote_addr(), &addr);
+    conn.write_all(b"ping").unwrap();
+    let mut buf = [0; 4];
+    conn.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+    t.join().unwrap();
+}
@@ -0,0 +1,108 @@
+//! Unsigned LEB128 varints, as used by multiformats and protobuf
+
+use std::io;
+use std::io::{Read, Write};
+
+use error::{Error, Result};
+
+/// Appends the varint encoding of `n` to `buf`
+pub fn encode_varint(mut n: u64, buf: &mut Vec<u8>) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+/// Decodes a varint from the start of `buf`
+///
+/// Returns the value and the number of bytes it took up.
+pub fn decode_varint(buf: &[u8]) -> Result<(u64, usize)> {
+    let mut n: u64 = 0;
+    for (i, &byte) in buf.iter().enumerate() {
+        if i >= 10 || (i == 9 && byte > 1) {
+            return Err(Error::Varint("varint is too large".to_owned()));
+        }
+        n |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((n, i + 1));
+        }
+    }
+    Err(Error::Varint("varint is truncated".to_owned()))
+}
+
+/// Reads a varint from a stream, one byte at a time
+///
+/// Returns `None` if the stream ends before the first byte.  If it ends part way through the
+/// varint, the error is an `io::ErrorKind::UnexpectedEof`.
+pub fn read_varint<R: Read>(r: &mut R) -> Result<Option<u64>> {
+    let mut buf = Vec::with_capacity(10);
+    loop {
+        let mut byte = [0];
+        let n = try!(r.read(&mut byte));
+        if n == 0 {
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended inside a varint")));
+        }
+        buf.push(byte[0]);
+        if byte[0] & 0x80 == 0 {
+            let (n, _) = try!(decode_varint(&buf));
+            return Ok(Some(n));
+        }
+        if buf.len() >= 10 {
+            return Err(Error::Varint("varint is too large".to_owned()));
+        }
+    }
+}
+
+/// Writes a varint to a stream
+pub fn write_varint<W: Write>(w: &mut W, n: u64) -> Result<()> {
+    let mut buf = Vec::with_capacity(10);
+    encode_varint(n, &mut buf);
+    try!(w.write_all(&buf));
+    Ok(())
+}
+
+
+#[test]
+fn test_varint() {
+    for &(n, ref bytes) in &[(0u64, vec![0u8]),
+                             (1, vec![1]),
+                             (127, vec![0x7f]),
+                             (128, vec![0x80, 0x01]),
+                             (300, vec![0xac, 0x02]),
+                             (::std::u64::MAX, vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01])] {
+        let mut buf = Vec::new();
+        encode_varint(n, &mut buf);
+        assert_eq!(buf, *bytes);
+        assert_eq!(decode_varint(&buf).unwrap(), (n, bytes.len()));
+    }
+
+    assert!(decode_varint(&[]).is_err());
+    assert!(decode_varint(&[0x80]).is_err());
+    assert!(decode_varint(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]).is_err());
+}
+
+#[test]
+fn test_read_write_varint() {
+    let mut buf = Vec::new();
+    write_varint(&mut buf, 300).unwrap();
+    write_varint(&mut buf, 1).unwrap();
+
+    let mut r = &buf[..];
+    assert_eq!(read_varint(&mut r).unwrap(), Some(300));
+    assert_eq!(read_varint(&mut r).unwrap(), Some(1));
+    assert_eq!(read_varint(&mut r).unwrap(), None);
+
+    let mut truncated = &[0x80u8][..];
+    match read_varint(&mut truncated) {
+        Err(Error::Io(ref e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+        other => panic!("unexpected result {:?}", other)
+    }
+}
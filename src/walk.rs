@@ -0,0 +1,244 @@
+//! Iterators that walk a DAG starting from a root node
+//!
+//! Nodes are loaded from the blockstore lazily, one per call to `next`.
+
+use std::collections::{HashMap, VecDeque};
+
+use blockstore::Blockstore;
+use error::{Error, Result};
+use multihash::{Multihash, MultihashBytes};
+use ::Node;
+
+/// The order in which nodes are visited
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    /// Pre-order: a node, then all of its first child's descendants, then its second child...
+    DepthFirst,
+    /// All nodes at depth 0, then all at depth 1, and so on
+    BreadthFirst,
+}
+
+/// Options controlling a walk
+#[derive(Clone, Debug)]
+pub struct WalkOptions {
+    pub order: Order,
+    /// Visit each distinct block only once, even if several nodes link to it
+    ///
+    /// Along with `max_depth`, a block first reached at the limit is still descended into if it
+    /// turns up again closer to the root, so which blocks are visited doesn't depend on `order`.
+    pub unique: bool,
+    /// Don't go deeper than this (the root is depth 0)
+    pub max_depth: Option<usize>,
+    /// Silently skip blocks that aren't in the store, instead of returning `Error::MissingBlock`
+    pub skip_missing: bool,
+}
+
+impl Default for WalkOptions {
+    fn default() -> WalkOptions {
+        WalkOptions{ order: Order::DepthFirst, unique: false, max_depth: None, skip_missing: false }
+    }
+}
+
+/// A node reached during a walk
+#[derive(Debug)]
+pub struct Visit {
+    /// The node, already loaded
+    pub node: Node,
+    /// How many links away from the root this node is, along the path it was first reached by
+    pub depth: usize,
+}
+
+/// An iterator over the nodes of a DAG
+pub struct Walk<'a, B: Blockstore + 'a> {
+    store: &'a B,
+    opts: WalkOptions,
+    pending: VecDeque<(MultihashBytes, usize)>,
+    /// With `unique`, the shallowest depth each block has been reached at
+    seen: HashMap<Vec<u8>, usize>,
+}
+
+/// Starts a walk at `root`
+pub fn walk<'a, B: Blockstore, M: Multihash>(store: &'a B, root: M, opts: WalkOptions) -> Result<Walk<'a, B>> {
+    let mut pending = VecDeque::new();
+    pending.push_back((MultihashBytes(try!(root.as_bytes())), 0));
+    Ok(Walk{ store: store, opts: opts, pending: pending, seen: HashMap::new() })
+}
+
+impl<'a, B: Blockstore> Walk<'a, B> {
+    fn next_pending(&mut self) -> Option<(MultihashBytes, usize)> {
+        match self.opts.order {
+            Order::DepthFirst => self.pending.pop_back(),
+            Order::BreadthFirst => self.pending.pop_front(),
+        }
+    }
+}
+
+impl<'a, B: Blockstore> Iterator for Walk<'a, B> {
+    type Item = Result<Visit>;
+
+    fn next(&mut self) -> Option<Result<Visit>> {
+        loop {
+            let (hash, depth) = match self.next_pending() {
+                Some(p) => p,
+                None => return None
+            };
+
+            // a block already visited, now reached closer to the root, only has its children
+            // looked at again, in case the depth limit cut them off before
+            let mut revisit = false;
+            if self.opts.unique {
+                match self.seen.get(&hash.0) {
+                    Some(&seen_depth) if depth >= seen_depth || self.opts.max_depth.is_none() => continue,
+                    Some(_) => revisit = true,
+                    None => {}
+                }
+                self.seen.insert(hash.0.clone(), depth);
+            }
+
+            let mut node = match Node::from_mh(&hash) {
+                Ok(n) => n,
+                Err(e) => return Some(Err(e))
+            };
+            match node.load_from(self.store) {
+                Ok(()) => {}
+                // any error was returned the first time
+                Err(_) if revisit => continue,
+                Err(Error::MissingBlock(_)) if self.opts.skip_missing => continue,
+                Err(e) => return Some(Err(e))
+            }
+
+            let descend = match self.opts.max_depth {
+                Some(max) => depth < max,
+                None => true
+            };
+            if descend {
+                let children = node.get_node().get_Links().iter()
+                    .map(|l| (MultihashBytes(l.get_Hash().to_vec()), depth + 1));
+                match self.opts.order {
+                    // pushed in reverse, so the first child is popped first
+                    Order::DepthFirst => {
+                        for child in children.rev() {
+                            self.pending.push_back(child);
+                        }
+                    }
+                    Order::BreadthFirst => self.pending.extend(children),
+                }
+            }
+
+            if revisit {
+                continue;
+            }
+            return Some(Ok(Visit{ node: node, depth: depth }));
+        }
+    }
+}
+
+
+#[cfg(test)]
+fn build_test_dag(store: &mut ::blockstore::MemBlockstore) -> (Node, Node, Node, Node) {
+    use directory::DirectoryBuilder;
+    use importer::{import_file, ImportOptions};
+
+    // root
+    //  |- dir (sub)
+    //  |   |- a (file)
+    //  |   `- b (file)
+    //  `- file (the same file as dir/a)
+    let a = import_file(store, &b"aaaa"[..], &ImportOptions::default()).unwrap();
+    let b = import_file(store, &b"bbbb"[..], &ImportOptions::default()).unwrap();
+    let mut sub = DirectoryBuilder::new();
+    sub.add_node("a", &a).unwrap();
+    sub.add_node("b", &b).unwrap();
+    let sub = sub.build(store).unwrap();
+    let mut root = DirectoryBuilder::new();
+    root.add_node("dir", &sub).unwrap();
+    root.add_node("file", &a).unwrap();
+    let root = root.build(store).unwrap();
+
+    (root, sub, a, b)
+}
+
+#[cfg(test)]
+fn walk_names<B: Blockstore>(store: &B, root: &Node, opts: WalkOptions) -> Vec<String> {
+    walk(store, &root.mh_bytes, opts).unwrap().map(|v| v.unwrap().node.mh_str.0).collect()
+}
+
+#[cfg(test)]
+fn names(nodes: &[&Node]) -> Vec<String> {
+    nodes.iter().map(|n| n.mh_str.0.clone()).collect()
+}
+
+#[test]
+fn test_walk_orders() {
+    use blockstore::MemBlockstore;
+
+    let mut store = MemBlockstore::new();
+    let (root, sub, a, b) = build_test_dag(&mut store);
+    let dfs = WalkOptions::default();
+    let bfs = WalkOptions{ order: Order::BreadthFirst, ..WalkOptions::default() };
+
+    assert_eq!(walk_names(&store, &root, dfs.clone()), names(&[&root, &sub, &a, &b, &a]));
+    assert_eq!(walk_names(&store, &root, bfs.clone()), names(&[&root, &sub, &a, &a, &b]));
+    assert_eq!(walk_names(&store, &root, WalkOptions{ unique: true, ..dfs.clone() }), names(&[&root, &sub, &a, &b]));
+    assert_eq!(walk_names(&store, &root, WalkOptions{ unique: true, ..bfs.clone() }), names(&[&root, &sub, &a, &b]));
+    assert_eq!(walk_names(&store, &root, WalkOptions{ max_depth: Some(1), ..dfs }), names(&[&root, &sub, &a]));
+    assert_eq!(walk_names(&store, &root, WalkOptions{ max_depth: Some(1), ..bfs }), names(&[&root, &sub, &a]));
+}
+
+#[test]
+fn test_walk_depths() {
+    use blockstore::MemBlockstore;
+
+    let mut store = MemBlockstore::new();
+    let (root, _, _, _) = build_test_dag(&mut store);
+    let depths = |opts: WalkOptions| -> Vec<usize> {
+        walk(&store, &root.mh_bytes, opts).unwrap().map(|v| v.unwrap().depth).collect()
+    };
+    assert_eq!(depths(WalkOptions::default()), vec![0, 1, 2, 2, 1]);
+    assert_eq!(depths(WalkOptions{ order: Order::BreadthFirst, ..WalkOptions::default() }), vec![0, 1, 1, 2, 2]);
+}
+
+#[test]
+fn test_walk_unique_with_max_depth() {
+    use blockstore::MemBlockstore;
+    use directory::DirectoryBuilder;
+
+    // root
+    //  |- x
+    //  |   `- deep (sub)
+    //  `- y (the same sub, one level up)
+    let mut store = MemBlockstore::new();
+    let (_, sub, a, b) = build_test_dag(&mut store);
+    let mut x = DirectoryBuilder::new();
+    x.add_node("deep", &sub).unwrap();
+    let x = x.build(&mut store).unwrap();
+    let mut root = DirectoryBuilder::new();
+    root.add_node("x", &x).unwrap();
+    root.add_node("y", &sub).unwrap();
+    let root = root.build(&mut store).unwrap();
+
+    // depth first reaches sub at the limit, under x, but still finds its children through y
+    let opts = WalkOptions{ unique: true, max_depth: Some(2), ..WalkOptions::default() };
+    assert_eq!(walk_names(&store, &root, opts.clone()), names(&[&root, &x, &sub, &a, &b]));
+    assert_eq!(walk_names(&store, &root, WalkOptions{ order: Order::BreadthFirst, ..opts }), names(&[&root, &x, &sub, &a, &b]));
+}
+
+#[test]
+fn test_walk_missing_blocks() {
+    use blockstore::MemBlockstore;
+
+    let mut store = MemBlockstore::new();
+    let (root, _, a, _) = build_test_dag(&mut store);
+    store.delete(&a.mh_bytes).unwrap();
+
+    let skipped: Vec<Visit> = walk(&store, &root.mh_bytes, WalkOptions{ skip_missing: true, ..WalkOptions::default() })
+        .unwrap().map(|v| v.unwrap()).collect();
+    assert_eq!(skipped.len(), 3);
+
+    let results: Vec<Result<Visit>> = walk(&store, &root.mh_bytes, WalkOptions::default()).unwrap().collect();
+    assert_eq!(results.len(), 5);
+    match results[2] {
+        Err(Error::MissingBlock(ref h)) => assert_eq!(*h, a.mh_str.0),
+        ref other => panic!("unexpected result {:?}", other)
+    }
+}